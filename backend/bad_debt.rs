@@ -0,0 +1,125 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use std::cell::RefCell;
+
+use crate::error::LendingError;
+use crate::roles::{require_role, Role};
+use crate::{interest, treasury};
+use crate::{memory, Memory, BAD_DEBT_EVENTS_MEMORY_ID, BAD_DEBT_STATE_MEMORY_ID, DEBT_ASSET};
+
+/// Running totals of protocol solvency
+#[derive(CandidType, Deserialize, Default, Clone)]
+pub struct BadDebtSummary {
+    /// ckUSDT moved from the treasury to cover future deficits
+    pub reserve: u64,
+    /// All deficits ever recorded
+    pub total_bad_debt: u64,
+    /// Part of the deficits written off from the reserve
+    pub total_covered: u64,
+    /// Deficits lenders carry until the reserve is funded
+    pub outstanding: u64,
+}
+
+/// One underwater liquidation
+#[derive(CandidType, Deserialize, Clone)]
+pub struct BadDebtEvent {
+    pub id: u64,
+    pub borrower: Principal,
+    pub debt: u64,
+    pub collateral_seized: u64,
+    pub deficit: u64,
+    pub covered_by_reserve: u64,
+    pub timestamp: u64,
+}
+
+impl_candid_storable!(BadDebtSummary, BadDebtEvent);
+
+thread_local! {
+    static SUMMARY: RefCell<StableCell<BadDebtSummary, Memory>> = RefCell::new(
+        StableCell::init(memory(BAD_DEBT_STATE_MEMORY_ID), BadDebtSummary::default())
+            .expect("Failed to init bad debt state")
+    );
+
+    static EVENTS: RefCell<StableBTreeMap<u64, BadDebtEvent, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(BAD_DEBT_EVENTS_MEMORY_ID))
+    );
+}
+
+fn update_summary(f: impl FnOnce(&mut BadDebtSummary)) {
+    SUMMARY.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let mut summary = cell.get().clone();
+        f(&mut summary);
+        cell.set(summary).expect("Failed to save bad debt state");
+    });
+}
+
+/// Move a liquidation deficit into the bad-debt ledger and write off as much as the reserve allows.
+/// Whatever the reserve cannot cover is taken from the lenders, must follow `accrue`.
+pub fn record_bad_debt(borrower: Principal, debt: u64, collateral_seized: u64, deficit: u64) {
+    let mut covered = 0;
+    update_summary(|s| {
+        covered = deficit.min(s.reserve);
+        s.reserve -= covered;
+        s.total_bad_debt += deficit;
+        s.total_covered += covered;
+        s.outstanding += deficit - covered;
+    });
+    interest::update_total_supply(DEBT_ASSET, 0, deficit - covered);
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let id = events.len();
+        events.insert(
+            id,
+            BadDebtEvent {
+                id,
+                borrower,
+                debt,
+                collateral_seized,
+                deficit,
+                covered_by_reserve: covered,
+                timestamp: ic_cdk::api::time(),
+            },
+        );
+    });
+
+    ic_cdk::println!(
+        "Bad debt of {} recorded for {}, {} covered by reserve",
+        deficit,
+        borrower.to_text(),
+        covered
+    );
+}
+
+// ===== Canister Methods ===== //
+#[update]
+fn fund_reserve(amount: u64) -> Result<(), LendingError> {
+    require_role(Role::Treasurer)?;
+    if amount == 0 {
+        return Err(LendingError::InvalidAmount);
+    }
+    interest::accrue(DEBT_ASSET);
+    treasury::take_revenue(DEBT_ASSET, amount)?;
+    // New funds pay back lenders for outstanding bad debt first
+    let mut write_off = 0;
+    update_summary(|s| {
+        write_off = amount.min(s.outstanding);
+        s.outstanding -= write_off;
+        s.total_covered += write_off;
+        s.reserve += amount - write_off;
+    });
+    interest::update_total_supply(DEBT_ASSET, write_off, 0);
+    Ok(())
+}
+
+#[query]
+fn get_bad_debt_summary() -> BadDebtSummary {
+    SUMMARY.with(|cell| cell.borrow().get().clone())
+}
+
+#[query]
+fn get_bad_debt_events() -> Vec<BadDebtEvent> {
+    EVENTS.with(|events| events.borrow().iter().map(|(_, e)| e).collect())
+}
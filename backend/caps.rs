@@ -0,0 +1,128 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::query;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::error::LendingError;
+use crate::{interest, oracle, risk};
+use crate::{memory, Asset, Memory, COLLATERAL_TOTALS_MEMORY_ID, DEBT_ASSET};
+
+/// Limits of one market, `None` means unlimited
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct MarketCaps {
+    /// Maximum collateral held by all positions together
+    pub collateral_cap: Option<u64>,
+    /// Maximum debt owed by all positions together
+    pub borrow_cap: Option<u64>,
+}
+
+/// Caps of one market next to its current usage
+#[derive(CandidType, Deserialize, Clone)]
+pub struct MarketCapsInfo {
+    pub caps: MarketCaps,
+    pub total_collateral: u64,
+    pub collateral_headroom: Option<u64>,
+    pub total_borrows: u64,
+    pub borrow_headroom: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct MarketCapsOverview {
+    pub markets: Vec<(Asset, MarketCapsInfo)>,
+    pub debt_ceiling: Option<u64>,
+    /// Debt of every market in debt asset units, `None` while a price is unavailable
+    pub total_debt: Option<u64>,
+    pub debt_headroom: Option<u64>,
+}
+
+thread_local! {
+    static COLLATERAL_TOTALS: RefCell<StableBTreeMap<Asset, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(COLLATERAL_TOTALS_MEMORY_ID))
+    );
+}
+
+pub fn total_collateral(asset: Asset) -> u64 {
+    COLLATERAL_TOTALS.with(|t| t.borrow().get(&asset).unwrap_or(0))
+}
+
+/// Track collateral added to or removed from positions
+pub fn update_total_collateral(asset: Asset, added: u64, removed: u64) {
+    COLLATERAL_TOTALS.with(|t| {
+        let mut map = t.borrow_mut();
+        let total = map.get(&asset).unwrap_or(0);
+        map.insert(asset, (total + added).saturating_sub(removed));
+    });
+}
+
+fn total_borrows(asset: Asset) -> u64 {
+    let market = interest::current(asset);
+    interest::to_debt(market.total_scaled_debt, market.index)
+}
+
+// Debt of every market valued in the debt asset, the ceiling applies across assets
+fn total_debt() -> Result<u64, LendingError> {
+    Asset::ALL.iter().try_fold(0, |total, asset| {
+        let borrows = total_borrows(*asset);
+        if borrows == 0 {
+            return Ok(total);
+        }
+        Ok(total + oracle::convert(borrows, *asset, DEBT_ASSET)?)
+    })
+}
+
+fn ensure_below(cap: Option<u64>, total: u64, amount: u64) -> Result<(), LendingError> {
+    match cap {
+        Some(cap) if total + amount > cap => Err(LendingError::CapExceeded { cap }),
+        _ => Ok(()),
+    }
+}
+
+pub fn ensure_collateral_cap(asset: Asset, amount: u64) -> Result<(), LendingError> {
+    let caps = risk::risk_params().market_caps(asset);
+    ensure_below(caps.collateral_cap, total_collateral(asset), amount)
+}
+
+/// Checks both the market borrow cap and the global debt ceiling
+pub fn ensure_borrow_cap(asset: Asset, amount: u64) -> Result<(), LendingError> {
+    let params = risk::risk_params();
+    let caps = params.market_caps(asset);
+    ensure_below(caps.borrow_cap, total_borrows(asset), amount)?;
+    if params.debt_ceiling.is_none() {
+        return Ok(());
+    }
+    let amount = oracle::convert(amount, asset, DEBT_ASSET)?;
+    ensure_below(params.debt_ceiling, total_debt()?, amount)
+}
+
+fn headroom(cap: Option<u64>, total: u64) -> Option<u64> {
+    cap.map(|cap| cap.saturating_sub(total))
+}
+
+// ===== Canister Methods ===== //
+#[query]
+fn get_market_caps() -> MarketCapsOverview {
+    let params = risk::risk_params();
+    let markets = Asset::ALL
+        .iter()
+        .map(|asset| {
+            let caps = params.market_caps(*asset);
+            let total_collateral = total_collateral(*asset);
+            let total_borrows = total_borrows(*asset);
+            let info = MarketCapsInfo {
+                collateral_headroom: headroom(caps.collateral_cap, total_collateral),
+                borrow_headroom: headroom(caps.borrow_cap, total_borrows),
+                caps,
+                total_collateral,
+                total_borrows,
+            };
+            (*asset, info)
+        })
+        .collect();
+    let total_debt = total_debt().ok();
+    MarketCapsOverview {
+        markets,
+        debt_ceiling: params.debt_ceiling,
+        total_debt,
+        debt_headroom: total_debt.and_then(|total| headroom(params.debt_ceiling, total)),
+    }
+}
@@ -0,0 +1,227 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::error::LendingError;
+use crate::pause::Operation;
+use crate::{memory, Memory, PositionKey, DELEGATIONS_MEMORY_ID, LOANS};
+
+/// What a delegate may do on a position
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum DelegateScope {
+    RepayOnly,
+    AddCollateralOnly,
+    /// Every position change except closing and transferring it
+    Full {
+        /// Total the delegate may borrow over the lifetime of the delegation
+        borrow_limit: u64,
+    },
+}
+
+impl DelegateScope {
+    fn permits(&self, operation: Operation) -> bool {
+        match self {
+            DelegateScope::RepayOnly => operation == Operation::Repay,
+            DelegateScope::AddCollateralOnly => operation == Operation::Deposit,
+            DelegateScope::Full { .. } => true,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct DelegationKey {
+    position_id: u64,
+    delegate: Principal,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Delegation {
+    pub position_id: u64,
+    pub owner: Principal,
+    pub delegate: Principal,
+    pub scope: DelegateScope,
+    /// Amount borrowed so far under a `Full` scope
+    pub borrowed: u64,
+    pub expires_at: u64,
+    pub granted_at: u64,
+}
+
+impl_candid_storable!(DelegationKey, Delegation);
+
+thread_local! {
+    static DELEGATIONS: RefCell<StableBTreeMap<DelegationKey, Delegation, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(DELEGATIONS_MEMORY_ID))
+    );
+}
+
+fn delegations_of(position_id: u64) -> Vec<Delegation> {
+    let first = DelegationKey {
+        position_id,
+        delegate: Principal::management_canister(),
+    };
+    DELEGATIONS.with(|d| {
+        d.borrow()
+            .range(first..)
+            .take_while(|(key, _)| key.position_id == position_id)
+            .map(|(_, delegation)| delegation)
+            .collect()
+    })
+}
+
+/// Position the caller may change with `operation`, either as owner or as a delegate.
+/// `amount` is checked against the borrow limit of a delegate.
+pub fn authorize(
+    position_id: u64,
+    operation: Operation,
+    amount: u64,
+) -> Result<PositionKey, LendingError> {
+    let caller = ic_cdk::api::msg_caller();
+    let own = PositionKey {
+        owner: caller,
+        position_id,
+    };
+    if LOANS.with(|loans| loans.borrow().contains_key(&own)) {
+        return Ok(own);
+    }
+
+    let delegation = DELEGATIONS
+        .with(|d| {
+            d.borrow().get(&DelegationKey {
+                position_id,
+                delegate: caller,
+            })
+        })
+        .ok_or(LendingError::NoPosition)?;
+    if ic_cdk::api::time() >= delegation.expires_at {
+        return Err(LendingError::DelegationExpired {
+            expired_at: delegation.expires_at,
+        });
+    }
+    if !delegation.scope.permits(operation) {
+        return Err(LendingError::NotAuthorized { operation });
+    }
+    if let (Operation::Borrow, DelegateScope::Full { borrow_limit }) =
+        (operation, &delegation.scope)
+    {
+        let available = borrow_limit.saturating_sub(delegation.borrowed);
+        if amount > available {
+            return Err(LendingError::DelegateLimitExceeded { available });
+        }
+    }
+    Ok(PositionKey {
+        owner: delegation.owner,
+        position_id,
+    })
+}
+
+/// Count a borrow made by the caller against their delegation, owners are not tracked
+pub fn record_borrow(position_id: u64, amount: u64) {
+    let key = DelegationKey {
+        position_id,
+        delegate: ic_cdk::api::msg_caller(),
+    };
+    DELEGATIONS.with(|d| {
+        let mut map = d.borrow_mut();
+        if let Some(mut delegation) = map.get(&key) {
+            delegation.borrowed += amount;
+            map.insert(key, delegation);
+        }
+    });
+}
+
+/// Give back borrow limit counted for a borrow that was not paid out
+pub fn release_borrow(position_id: u64, amount: u64) {
+    let key = DelegationKey {
+        position_id,
+        delegate: ic_cdk::api::msg_caller(),
+    };
+    DELEGATIONS.with(|d| {
+        let mut map = d.borrow_mut();
+        if let Some(mut delegation) = map.get(&key) {
+            delegation.borrowed = delegation.borrowed.saturating_sub(amount);
+            map.insert(key, delegation);
+        }
+    });
+}
+
+/// Revoke every delegate of a position, used when it is closed or changes owner
+pub fn clear(position_id: u64) {
+    for delegation in delegations_of(position_id) {
+        DELEGATIONS.with(|d| {
+            d.borrow_mut().remove(&DelegationKey {
+                position_id,
+                delegate: delegation.delegate,
+            })
+        });
+    }
+}
+
+// ===== Canister Methods ===== //
+// Authorizing a delegate again replaces its scope and resets its borrowed amount
+#[update]
+fn authorize_delegate(
+    position_id: u64,
+    delegate: Principal,
+    scope: DelegateScope,
+    expires_at: u64,
+) -> Result<(), LendingError> {
+    let owner = ic_cdk::api::msg_caller();
+    if delegate == owner || delegate == Principal::anonymous() {
+        return Err(LendingError::InvalidRecipient);
+    }
+    let now = ic_cdk::api::time();
+    if expires_at <= now {
+        return Err(LendingError::InvalidParams(
+            "Expiry must be in the future".to_string(),
+        ));
+    }
+    let key = PositionKey { owner, position_id };
+    if !LOANS.with(|loans| loans.borrow().contains_key(&key)) {
+        return Err(LendingError::NoPosition);
+    }
+
+    let delegation = Delegation {
+        position_id,
+        owner,
+        delegate,
+        scope,
+        borrowed: 0,
+        expires_at,
+        granted_at: now,
+    };
+    DELEGATIONS.with(|d| {
+        d.borrow_mut().insert(
+            DelegationKey {
+                position_id,
+                delegate,
+            },
+            delegation,
+        )
+    });
+    Ok(())
+}
+
+#[update]
+fn revoke_delegate(position_id: u64, delegate: Principal) -> Result<(), LendingError> {
+    let owner = ic_cdk::api::msg_caller();
+    let key = DelegationKey {
+        position_id,
+        delegate,
+    };
+    DELEGATIONS.with(|d| {
+        let mut map = d.borrow_mut();
+        match map.get(&key) {
+            Some(delegation) if delegation.owner == owner => {
+                map.remove(&key);
+                Ok(())
+            }
+            _ => Err(LendingError::DelegationNotFound),
+        }
+    })
+}
+
+#[query]
+fn list_delegates(position_id: u64) -> Vec<Delegation> {
+    delegations_of(position_id)
+}
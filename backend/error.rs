@@ -0,0 +1,113 @@
+use candid::{CandidType, Deserialize, Nat};
+
+use crate::pause::Operation;
+use crate::price::Price;
+use crate::roles::Role;
+use crate::{TransferError, TransferFromError};
+
+/// Failure of a lending endpoint that clients can branch on
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum LendingError {
+    Paused {
+        operation: Operation,
+    },
+    InvalidAmount,
+    NoPosition,
+    /// Positions can only be closed once their debt is repaid
+    PositionHasDebt {
+        debt: u64,
+    },
+    /// Positions cannot be handed to their owner or the anonymous principal
+    InvalidRecipient,
+    PositionTransferNotFound,
+    /// The caller is a delegate of the position without permission for this operation
+    NotAuthorized {
+        operation: Operation,
+    },
+    DelegationExpired {
+        expired_at: u64,
+    },
+    /// Borrowing would take the delegate above its borrow limit
+    DelegateLimitExceeded {
+        available: u64,
+    },
+    DelegationNotFound,
+    InsufficientCollateral {
+        available: u64,
+    },
+    /// Borrowing would take the debt above the LTV limit
+    BorrowLimitExceeded {
+        available: u64,
+    },
+    /// Withdrawing would take the debt above the LTV limit
+    WithdrawLimitExceeded {
+        available: u64,
+    },
+    RepayExceedsDebt {
+        debt: u64,
+    },
+    NotLiquidatable,
+    InsufficientLiquidity {
+        available: u64,
+    },
+    CapExceeded {
+        cap: u64,
+    },
+    InsufficientShares {
+        owned: u64,
+    },
+    InsufficientTreasuryBalance {
+        balance: u64,
+    },
+    InvalidParams(String),
+    ProposalNotFound,
+    ProposalNotPending,
+    ProposalTimelocked {
+        executable_at: u64,
+    },
+    /// The caller does not hold the role the endpoint requires
+    Unauthorized {
+        role: Role,
+    },
+    RoleNotGranted,
+    /// A price is missing or older than the configured max age
+    OracleStale {
+        last_updated: u64,
+    },
+    /// The latest price moved too far and waits for confirmation, borrowing is paused
+    PriceQuarantined {
+        feed: String,
+        price: Price,
+    },
+    NoQuarantinedPrice,
+    PriceFeedNotFound,
+    /// A cross rate does not fit into a price
+    PriceOutOfRange,
+    /// Too few price sources answered close enough to the median
+    PriceSourcesDisagree {
+        agreeing: u32,
+        required: u32,
+    },
+    /// A flash loan of the same asset has not finished yet
+    FlashLoanInProgress,
+    /// The receiver is not on the governance allowlist or still owes an earlier loan
+    FlashLoanReceiverNotAllowed,
+    /// The flash loan receiver has not approved principal, fee and ledger fee
+    FlashLoanNotApproved {
+        required: u64,
+    },
+    FlashLoanNotRepaid {
+        shortfall: u64,
+    },
+    NoFlashLoanDefault,
+    /// A payout would not even cover the ledger fee taken out of it
+    AmountBelowLedgerFee {
+        fee: Nat,
+    },
+    /// The ledger rejected a transfer
+    Ledger(TransferError),
+    /// The ledger rejected pulling funds through an ICRC-2 approval
+    LedgerTransferFrom(TransferFromError),
+    /// An inter-canister call failed before reaching the ledger logic
+    CallFailed(String),
+}
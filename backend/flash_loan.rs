@@ -0,0 +1,234 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::call::Call;
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::error::LendingError;
+use crate::pause::{self, Operation};
+use crate::{icrc1_fee, icrc1_transfer, icrc2_allowance, icrc2_transfer_from, Account, Asset};
+use crate::{interest, pool, risk, treasury};
+use crate::{memory, Memory, FLASH_LOAN_DEFAULTS_MEMORY_ID};
+
+// Method the receiver canister must implement
+const CALLBACK_METHOD: &str = "on_flash_loan";
+
+/// Argument passed to the receiver's `on_flash_loan`
+#[derive(CandidType, Deserialize, Clone)]
+pub struct FlashLoanCallback {
+    pub initiator: Principal,
+    pub asset: Asset,
+    /// Owed principal, the ledger fee of the payout was taken out of what arrived
+    pub amount: u64,
+    pub fee: u64,
+    /// Charged by the ledger on the payout and the repayment, principal plus
+    /// both fees must be approved to this canister before returning
+    pub ledger_fee: Nat,
+    pub payload: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct FlashLoanReceipt {
+    pub amount: u64,
+    pub fee: u64,
+    pub transfer_block: Nat,
+    pub repayment_block: Nat,
+}
+
+/// Flash loan a receiver took and did not pay back
+#[derive(CandidType, Deserialize, Clone)]
+pub struct FlashLoanDefault {
+    pub receiver: Principal,
+    pub asset: Asset,
+    pub amount: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct DefaultKey {
+    receiver: Principal,
+    asset: Asset,
+}
+
+impl_candid_storable!(DefaultKey);
+
+thread_local! {
+    // Liquidity lent out by flash loans still awaiting repayment
+    static IN_FLIGHT: RefCell<BTreeMap<Asset, u64>> = const { RefCell::new(BTreeMap::new()) };
+
+    // Unpaid principal stays owed by the receiver instead of being written off against lenders
+    static DEFAULTS: RefCell<StableBTreeMap<DefaultKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(FLASH_LOAN_DEFAULTS_MEMORY_ID))
+    );
+}
+
+pub fn in_flight(asset: Asset) -> u64 {
+    IN_FLIGHT.with(|f| f.borrow().get(&asset).copied().unwrap_or(0))
+}
+
+/// Liquidity receivers failed to return, it cannot be lent until they repay
+pub fn unpaid(asset: Asset) -> u64 {
+    DEFAULTS.with(|d| {
+        d.borrow()
+            .iter()
+            .filter(|(key, _)| key.asset == asset)
+            .map(|(_, amount)| amount)
+            .sum()
+    })
+}
+
+fn in_default(receiver: Principal) -> bool {
+    DEFAULTS.with(|d| d.borrow().iter().any(|(key, _)| key.receiver == receiver))
+}
+
+fn record_default(receiver: Principal, asset: Asset, amount: u64) {
+    DEFAULTS.with(|d| {
+        let mut defaults = d.borrow_mut();
+        let key = DefaultKey { receiver, asset };
+        let owed = defaults.get(&key).unwrap_or(0);
+        defaults.insert(key, owed + amount);
+    });
+}
+
+fn release(asset: Asset) {
+    IN_FLIGHT.with(|f| f.borrow_mut().remove(&asset));
+}
+
+async fn lend(
+    asset: Asset,
+    amount: u64,
+    fee: u64,
+    receiver: Account,
+    payload: Vec<u8>,
+) -> Result<FlashLoanReceipt, LendingError> {
+    let owed = amount + fee;
+    // `icrc2_transfer_from` takes the ledger fee out of the allowance as well
+    let ledger_fee = icrc1_fee(asset).await?;
+    let required = Nat::from(owed) + ledger_fee.clone();
+    let allowance = icrc2_allowance(asset, receiver.clone()).await?;
+    if allowance < required {
+        return Err(LendingError::FlashLoanNotApproved {
+            required: u64::try_from(required.0).unwrap_or(u64::MAX),
+        });
+    }
+
+    let transfer_block = icrc1_transfer(asset, receiver.clone(), Nat::from(amount)).await?;
+
+    // The callback outcome does not matter, only the repayment does
+    let callback = FlashLoanCallback {
+        initiator: ic_cdk::api::msg_caller(),
+        asset,
+        amount,
+        fee,
+        ledger_fee,
+        payload,
+    };
+    if let Err(e) = Call::unbounded_wait(receiver.owner, CALLBACK_METHOD)
+        .with_arg(callback)
+        .await
+    {
+        ic_cdk::println!("Flash loan callback on {} failed: {}", receiver.owner, e);
+    }
+
+    match icrc2_transfer_from(asset, receiver.clone(), Nat::from(owed)).await {
+        Ok(repayment_block) => Ok(FlashLoanReceipt {
+            amount,
+            fee,
+            transfer_block,
+            repayment_block,
+        }),
+        Err(e) => {
+            // The receiver owes the principal, its liquidity stays locked until repaid
+            record_default(receiver.owner, asset, amount);
+            ic_cdk::println!(
+                "Flash loan of {} {:?} to {} was not repaid: {:?}",
+                amount,
+                asset,
+                receiver.owner,
+                e
+            );
+            Err(LendingError::FlashLoanNotRepaid { shortfall: amount })
+        }
+    }
+}
+
+// ===== Canister Methods ===== //
+#[update]
+async fn flash_loan(
+    asset: Asset,
+    amount: u64,
+    receiver_canister: Principal,
+    payload: Vec<u8>,
+) -> Result<FlashLoanReceipt, LendingError> {
+    pause::ensure_active(Operation::FlashLoan)?;
+    if amount == 0 {
+        return Err(LendingError::InvalidAmount);
+    }
+    if !risk::risk_params()
+        .flash_loan_receivers
+        .contains(&receiver_canister)
+        || in_default(receiver_canister)
+    {
+        return Err(LendingError::FlashLoanReceiverNotAllowed);
+    }
+    if in_flight(asset) > 0 {
+        return Err(LendingError::FlashLoanInProgress);
+    }
+    interest::accrue(asset);
+    let available = pool::available_liquidity(asset);
+    if amount > available {
+        return Err(LendingError::InsufficientLiquidity { available });
+    }
+
+    // Hold the liquidity so nothing else lends it while the loan is out
+    IN_FLIGHT.with(|f| f.borrow_mut().insert(asset, amount));
+    let fee = risk::risk_params().flash_loan_fee(amount);
+    let receiver = Account {
+        owner: receiver_canister,
+        subaccount: None,
+    };
+    let result = lend(asset, amount, fee, receiver, payload).await;
+    release(asset);
+
+    if result.is_ok() {
+        // The fee is shared between lenders and the treasury like interest
+        interest::accrue(asset);
+        let reserves = treasury::reserve_share(asset, fee);
+        interest::update_total_supply(asset, fee - reserves, 0);
+        treasury::record_flash_loan_fee(asset, reserves);
+    }
+    result
+}
+
+// Anyone can settle a default once the receiver has approved the owed amount plus the ledger fee
+#[update]
+async fn repay_flash_loan_default(receiver: Principal, asset: Asset) -> Result<Nat, LendingError> {
+    let key = DefaultKey { receiver, asset };
+    // Remove before the pull so concurrent calls cannot collect twice
+    let owed = DEFAULTS
+        .with(|d| d.borrow_mut().remove(&key))
+        .ok_or(LendingError::NoFlashLoanDefault)?;
+    let from = Account {
+        owner: receiver,
+        subaccount: None,
+    };
+    let result = icrc2_transfer_from(asset, from, Nat::from(owed)).await;
+    if result.is_err() {
+        record_default(receiver, asset, owed);
+    }
+    result
+}
+
+#[query]
+fn get_flash_loan_defaults() -> Vec<FlashLoanDefault> {
+    DEFAULTS.with(|d| {
+        d.borrow()
+            .iter()
+            .map(|(key, amount)| FlashLoanDefault {
+                receiver: key.receiver,
+                asset: key.asset,
+                amount,
+            })
+            .collect()
+    })
+}
@@ -0,0 +1,211 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use std::cell::RefCell;
+
+use crate::error::LendingError;
+use crate::oracle::{self, OracleConfig};
+use crate::price_refresh;
+use crate::risk::{self, RiskParams};
+use crate::roles::{has_role, require_role, Role};
+use crate::{memory, set_ledger_id, Asset, Memory, PROPOSALS_MEMORY_ID, TIMELOCK_MEMORY_ID};
+
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+const DEFAULT_DELAY: u64 = 48 * NANOS_PER_HOUR;
+const MAX_DELAY: u64 = 30 * 24 * NANOS_PER_HOUR;
+
+/// Parameter change waiting in the timelock
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ProposalAction {
+    RiskParams(RiskParams),
+    LedgerId {
+        asset: Asset,
+        ledger_id: Principal,
+    },
+    /// New timelock delay in nanoseconds
+    TimelockDelay(u64),
+    OracleConfig(OracleConfig),
+}
+
+impl ProposalAction {
+    // Risk managers may propose risk parameters, every other change needs an admin
+    fn proposer_role(&self) -> Role {
+        match self {
+            ProposalAction::RiskParams(_) => Role::RiskManager,
+            _ => Role::Admin,
+        }
+    }
+
+    fn validate(&self) -> Result<(), LendingError> {
+        match self {
+            ProposalAction::RiskParams(params) => {
+                params.validate().map_err(LendingError::InvalidParams)
+            }
+            ProposalAction::LedgerId { ledger_id, .. } => {
+                if *ledger_id == Principal::anonymous() {
+                    return Err(LendingError::InvalidParams(
+                        "Ledger cannot be the anonymous principal".to_string(),
+                    ));
+                }
+                Ok(())
+            }
+            ProposalAction::OracleConfig(config) => {
+                config.validate().map_err(LendingError::InvalidParams)
+            }
+            ProposalAction::TimelockDelay(delay) => {
+                if *delay > MAX_DELAY {
+                    return Err(LendingError::InvalidParams(
+                        "Timelock delay cannot exceed 30 days".to_string(),
+                    ));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum ProposalStatus {
+    Pending,
+    Executed { executed_at: u64 },
+    Cancelled { cancelled_by: Principal },
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Proposal {
+    pub id: u64,
+    pub proposer: Principal,
+    pub action: ProposalAction,
+    pub created_at: u64,
+    /// Earliest time the proposal can be executed
+    pub executable_at: u64,
+    pub status: ProposalStatus,
+}
+
+impl_candid_storable!(Proposal);
+
+thread_local! {
+    static TIMELOCK_DELAY: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(memory(TIMELOCK_MEMORY_ID), DEFAULT_DELAY)
+            .expect("Failed to init timelock delay")
+    );
+
+    static PROPOSALS: RefCell<StableBTreeMap<u64, Proposal, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(PROPOSALS_MEMORY_ID))
+    );
+}
+
+fn timelock_delay() -> u64 {
+    TIMELOCK_DELAY.with(|d| *d.borrow().get())
+}
+
+fn pending_proposal(id: u64) -> Result<Proposal, LendingError> {
+    let proposal = PROPOSALS
+        .with(|p| p.borrow().get(&id))
+        .ok_or(LendingError::ProposalNotFound)?;
+    if proposal.status != ProposalStatus::Pending {
+        return Err(LendingError::ProposalNotPending);
+    }
+    Ok(proposal)
+}
+
+// Admins can submit and cancel any proposal
+fn require_proposer(action: &ProposalAction) -> Result<(), LendingError> {
+    if has_role(ic_cdk::api::msg_caller(), Role::Admin) {
+        return Ok(());
+    }
+    require_role(action.proposer_role())
+}
+
+fn save(proposal: Proposal) {
+    PROPOSALS.with(|p| p.borrow_mut().insert(proposal.id, proposal));
+}
+
+// ===== Canister Methods ===== //
+#[update]
+fn submit_proposal(action: ProposalAction) -> Result<u64, LendingError> {
+    require_proposer(&action)?;
+    action.validate()?;
+    let now = ic_cdk::api::time();
+    let id = PROPOSALS.with(|p| p.borrow().last_key_value().map_or(0, |(id, _)| id + 1));
+    save(Proposal {
+        id,
+        proposer: ic_cdk::api::msg_caller(),
+        action,
+        created_at: now,
+        executable_at: now + timelock_delay(),
+        status: ProposalStatus::Pending,
+    });
+    Ok(id)
+}
+
+#[update]
+fn cancel_proposal(id: u64) -> Result<(), LendingError> {
+    let mut proposal = pending_proposal(id)?;
+    require_proposer(&proposal.action)?;
+    proposal.status = ProposalStatus::Cancelled {
+        cancelled_by: ic_cdk::api::msg_caller(),
+    };
+    save(proposal);
+    Ok(())
+}
+
+// Anyone can execute a proposal once its delay has passed
+#[update]
+fn execute_proposal(id: u64) -> Result<(), LendingError> {
+    let mut proposal = pending_proposal(id)?;
+    let now = ic_cdk::api::time();
+    if now < proposal.executable_at {
+        return Err(LendingError::ProposalTimelocked {
+            executable_at: proposal.executable_at,
+        });
+    }
+
+    match proposal.action.clone() {
+        ProposalAction::RiskParams(params) => {
+            risk::set_risk_params(params, proposal.proposer).map_err(LendingError::InvalidParams)?
+        }
+        ProposalAction::LedgerId { asset, ledger_id } => set_ledger_id(asset, ledger_id),
+        ProposalAction::OracleConfig(config) => {
+            oracle::set_config(config);
+            // Feeds and their intervals may have changed
+            price_refresh::schedule();
+        }
+        ProposalAction::TimelockDelay(delay) => {
+            TIMELOCK_DELAY
+                .with(|d| d.borrow_mut().set(delay))
+                .expect("Failed to save timelock delay");
+        }
+    }
+
+    proposal.status = ProposalStatus::Executed { executed_at: now };
+    save(proposal);
+    Ok(())
+}
+
+#[query]
+fn get_timelock_delay() -> u64 {
+    timelock_delay()
+}
+
+#[query]
+fn get_proposal(id: u64) -> Option<Proposal> {
+    PROPOSALS.with(|p| p.borrow().get(&id))
+}
+
+#[query]
+fn list_proposals() -> Vec<Proposal> {
+    PROPOSALS.with(|p| p.borrow().iter().map(|(_, proposal)| proposal).collect())
+}
+
+/// Changes that will take effect once their delay passes
+#[query]
+fn list_pending_proposals() -> Vec<Proposal> {
+    PROPOSALS.with(|p| {
+        p.borrow()
+            .iter()
+            .map(|(_, proposal)| proposal)
+            .filter(|proposal| proposal.status == ProposalStatus::Pending)
+            .collect()
+    })
+}
@@ -0,0 +1,183 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::query;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::{memory, Asset, Memory, BORROW_INDICES_MEMORY_ID};
+use crate::{rate_model, treasury};
+
+// Indices are fixed-point numbers with 18 decimals
+pub const RAY: u128 = 1_000_000_000_000_000_000;
+const NANOS_PER_YEAR: u128 = 365 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Cumulative borrow index of one asset
+#[derive(CandidType, Deserialize, Clone)]
+pub struct BorrowIndex {
+    /// Debt owed per unit of scaled debt, in RAY
+    pub index: u128,
+    /// Time of the last accrual in nanoseconds
+    pub last_accrual: u64,
+    /// Sum of the scaled debt of every position
+    pub total_scaled_debt: u128,
+    /// Liquidity provided by lenders, including the part lent out
+    pub total_supply: u64,
+}
+
+impl BorrowIndex {
+    // Empty market that starts accruing at `now`
+    fn new(now: u64) -> Self {
+        BorrowIndex {
+            index: RAY,
+            last_accrual: now,
+            total_scaled_debt: 0,
+            total_supply: 0,
+        }
+    }
+
+    // Index grown by the interest of the time elapsed since the last accrual,
+    // along with the part of that interest kept as protocol reserves
+    fn accrued(mut self, asset: Asset, now: u64) -> (Self, u64) {
+        let mut reserves = 0;
+        let elapsed = now.saturating_sub(self.last_accrual) as u128;
+        if elapsed > 0 {
+            let total_borrows = to_debt(self.total_scaled_debt, self.index);
+            let rate = rate_model::borrow_rate_ray(asset, self.total_supply, total_borrows);
+            let interest = rate * elapsed / NANOS_PER_YEAR;
+            self.index += self.index * interest / RAY;
+            self.last_accrual = now;
+            // Interest owed by borrowers is earned by lenders minus the reserve factor
+            let earned = to_debt(self.total_scaled_debt, self.index) - total_borrows;
+            reserves = treasury::reserve_share(asset, earned);
+            self.total_supply += earned - reserves;
+        }
+        (self, reserves)
+    }
+}
+
+impl_candid_storable!(BorrowIndex);
+
+thread_local! {
+    static INDICES: RefCell<StableBTreeMap<Asset, BorrowIndex, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(BORROW_INDICES_MEMORY_ID))
+    );
+}
+
+fn stored(asset: Asset) -> BorrowIndex {
+    INDICES
+        .with(|i| i.borrow().get(&asset))
+        .unwrap_or_else(|| BorrowIndex::new(ic_cdk::api::time()))
+}
+
+fn accrued(asset: Asset) -> (BorrowIndex, u64) {
+    stored(asset).accrued(asset, ic_cdk::api::time())
+}
+
+/// Up-to-date index without persisting it, for queries
+pub fn current(asset: Asset) -> BorrowIndex {
+    accrued(asset).0
+}
+
+/// Accrue interest on `asset` and return the new index
+pub fn accrue(asset: Asset) -> u128 {
+    let (state, reserves) = accrued(asset);
+    let index = state.index;
+    INDICES.with(|i| i.borrow_mut().insert(asset, state));
+    treasury::record_interest(asset, reserves);
+    index
+}
+
+/// Track scaled debt added to or removed from positions, must follow `accrue`
+pub fn update_total_scaled_debt(asset: Asset, added: u128, removed: u128) {
+    let mut state = stored(asset);
+    state.total_scaled_debt = (state.total_scaled_debt + added).saturating_sub(removed);
+    INDICES.with(|i| i.borrow_mut().insert(asset, state));
+}
+
+/// Track liquidity supplied to or redeemed from the pool, must follow `accrue`
+pub fn update_total_supply(asset: Asset, added: u64, removed: u64) {
+    let mut state = stored(asset);
+    state.total_supply = (state.total_supply + added).saturating_sub(removed);
+    INDICES.with(|i| i.borrow_mut().insert(asset, state));
+}
+
+/// Scaled debt for `amount`, rounded up so the protocol never loses dust
+pub fn to_scaled(amount: u64, index: u128) -> u128 {
+    (amount as u128 * RAY).div_ceil(index)
+}
+
+/// Debt owed for `scaled` debt at `index`, rounded up
+pub fn to_debt(scaled: u128, index: u128) -> u64 {
+    (scaled * index).div_ceil(RAY) as u64
+}
+
+// ===== Canister Methods ===== //
+#[query]
+fn get_borrow_index(asset: Asset) -> BorrowIndex {
+    current(asset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YEAR: u64 = NANOS_PER_YEAR as u64;
+
+    // ckUSDT market with `borrows` lent out of `supply` at index 1
+    fn market(supply: u64, borrows: u64) -> BorrowIndex {
+        BorrowIndex {
+            total_scaled_debt: to_scaled(borrows, RAY),
+            total_supply: supply,
+            ..BorrowIndex::new(0)
+        }
+    }
+
+    #[test]
+    fn index_grows_by_the_yearly_rate() {
+        // 50% utilization is 2.5% a year on the default ckUSDT curve
+        let (market, reserves) = market(1_000_000, 500_000).accrued(Asset::CkUSDT, YEAR);
+        assert_eq!(market.index, RAY + RAY / 40);
+        assert_eq!(market.last_accrual, YEAR);
+        assert_eq!(to_debt(market.total_scaled_debt, market.index), 512_500);
+        assert_eq!(reserves, 1_250);
+        assert_eq!(market.total_supply, 1_011_250);
+    }
+
+    #[test]
+    fn nothing_accrues_without_elapsed_time() {
+        let (market, reserves) = market(1_000_000, 500_000).accrued(Asset::CkUSDT, 0);
+        assert_eq!(market.index, RAY);
+        assert_eq!(market.total_supply, 1_000_000);
+        assert_eq!(reserves, 0);
+    }
+
+    #[test]
+    fn interest_is_split_between_reserves_and_lenders() {
+        let before = market(777_777, 333_333);
+        let borrowed = to_debt(before.total_scaled_debt, before.index);
+        let (after, reserves) = before.accrued(Asset::CkUSDT, YEAR / 4);
+        let earned = to_debt(after.total_scaled_debt, after.index) - borrowed;
+        assert!(earned > 0);
+        // The default reserve factor is 10%, rounded down in favor of lenders
+        assert_eq!(reserves, earned / 10);
+        assert_eq!(after.total_supply - 777_777 + reserves, earned);
+    }
+
+    #[test]
+    fn scaled_round_trips_never_undercharge() {
+        for index in [RAY, RAY + 1, RAY + RAY / 40 + 7, 3 * RAY - 1] {
+            for amount in [1, 7, 999_999, 123_456_789, u64::MAX / 4] {
+                let debt = to_debt(to_scaled(amount, index), index);
+                assert!(debt >= amount, "{} at {}", amount, index);
+                // Rounding costs at most one unit of scaled debt
+                assert!((debt - amount) as u128 <= index / RAY + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn debt_rounds_up() {
+        assert_eq!(to_debt(1, RAY + 1), 2);
+        assert_eq!(to_debt(3, RAY), 3);
+        assert_eq!(to_scaled(1, 3 * RAY), 1);
+    }
+}
@@ -1,41 +1,197 @@
-// mod oracle;
 // mod state;
 
-// use crate::oracle::get_token_price;
+// Candid-encoded `Storable` for types kept in stable memory
+macro_rules! impl_candid_storable {
+    ($($t:ty),* $(,)?) => {
+        $(
+            impl ic_stable_structures::Storable for $t {
+                const BOUND: ic_stable_structures::storable::Bound =
+                    ic_stable_structures::storable::Bound::Unbounded;
+
+                fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+                    candid::encode_one(self).unwrap().into()
+                }
+
+                fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+                    candid::decode_one(&bytes).unwrap()
+                }
+            }
+        )*
+    };
+}
+
+mod bad_debt;
+mod caps;
+mod delegation;
+mod error;
+mod flash_loan;
+mod governance;
+mod interest;
+mod oracle;
+mod pause;
+mod pool;
+mod position_transfer;
+mod price;
+mod price_breaker;
+mod price_history;
+mod price_refresh;
+mod rate_model;
+mod risk;
+mod roles;
+mod treasury;
+mod xrc;
+
+use bad_debt::{BadDebtEvent, BadDebtSummary};
 use candid::{CandidType, Deserialize, Nat, Principal};
+use caps::MarketCapsOverview;
+use delegation::{DelegateScope, Delegation};
+use error::LendingError;
+use flash_loan::{FlashLoanDefault, FlashLoanReceipt};
+use governance::{Proposal, ProposalAction};
+use ic_cdk::call::Call;
+use ic_cdk::management_canister::{HttpRequestResult, TransformArgs};
 use ic_cdk_macros::export_candid;
 use ic_cdk_macros::*;
 use ic_stable_structures::{
     memory_manager::{MemoryId, MemoryManager, VirtualMemory},
-    DefaultMemoryImpl, StableBTreeMap, Storable,
+    DefaultMemoryImpl, StableBTreeMap, StableCell,
 };
+use interest::BorrowIndex;
+use oracle::{OracleConfig, PriceInfo};
+use pause::{Operation, PauseState};
+use pool::{PoolInfo, SupplyBalance};
+use position_transfer::PositionTransfer;
+use price::Price;
+use price_breaker::{BreakerEvent, Quarantine};
+use price_history::PricePoint;
+use price_refresh::RefreshStatus;
+use rate_model::Rates;
+use risk::{RiskParams, RiskParamsChange};
+use roles::{Role, RoleAssignment};
 use serde::Serialize;
+use treasury::ProtocolRevenue;
 // use state::*;
 use std::cell::RefCell;
 
-// akses local
-// use crate::oracle::get_token_price;
+// ===== Constants ===== //
+const CKTESTBTC_CANISTER_ID: &str = "mc6ru-gyaaa-aaaar-qaaaq-cai";
+const CKUSDT_CANISTER_ID: &str = "cngnf-vqaaa-aaaar-qag4q-cai";
 
-// akses global
-// pub use crate::oracle::{get_token_price, set_token_price};
+// Denominator of every rate and share given in basis points
+pub(crate) const BPS: u64 = 10_000;
 
-// ===== Constants ===== //
-// const CKTESTBTC_CANISTER_ID: &str = "mc6ru-gyaaa-aaaar-qaaaq-cai";
+// Collateral is held in ckBTC and loans are drawn in ckUSDT
+const COLLATERAL_ASSET: Asset = Asset::CkBTC;
+const DEBT_ASSET: Asset = Asset::CkUSDT;
+
+// ===== Stable Memory IDs ===== //
+// Single loan per principal from before positions, emptied by the upgrade migration
+const LEGACY_LOANS_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub(crate) const BAD_DEBT_STATE_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub(crate) const BAD_DEBT_EVENTS_MEMORY_ID: MemoryId = MemoryId::new(2);
+pub(crate) const BORROW_INDICES_MEMORY_ID: MemoryId = MemoryId::new(3);
+// MemoryId 4 held the rate models before they moved into the risk params
+pub(crate) const POOL_SHARES_MEMORY_ID: MemoryId = MemoryId::new(5);
+pub(crate) const SUPPLIER_SHARES_MEMORY_ID: MemoryId = MemoryId::new(6);
+// MemoryId 7 held the reserve factors before they moved into the risk params
+pub(crate) const TREASURY_MEMORY_ID: MemoryId = MemoryId::new(8);
+pub(crate) const RISK_PARAMS_MEMORY_ID: MemoryId = MemoryId::new(9);
+pub(crate) const RISK_PARAMS_AUDIT_MEMORY_ID: MemoryId = MemoryId::new(10);
+pub(crate) const ROLES_MEMORY_ID: MemoryId = MemoryId::new(11);
+const LEDGER_IDS_MEMORY_ID: MemoryId = MemoryId::new(12);
+pub(crate) const TIMELOCK_MEMORY_ID: MemoryId = MemoryId::new(13);
+pub(crate) const PROPOSALS_MEMORY_ID: MemoryId = MemoryId::new(14);
+pub(crate) const PAUSE_STATE_MEMORY_ID: MemoryId = MemoryId::new(15);
+pub(crate) const COLLATERAL_TOTALS_MEMORY_ID: MemoryId = MemoryId::new(16);
+const LOANS_MEMORY_ID: MemoryId = MemoryId::new(17);
+const NEXT_POSITION_ID_MEMORY_ID: MemoryId = MemoryId::new(18);
+pub(crate) const POSITION_TRANSFERS_MEMORY_ID: MemoryId = MemoryId::new(19);
+pub(crate) const DELEGATIONS_MEMORY_ID: MemoryId = MemoryId::new(20);
+// MemoryId 21 held the collateral price before feeds were keyed by symbol
+pub(crate) const ORACLE_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(22);
+pub(crate) const PRICE_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(23);
+pub(crate) const PRICE_HISTORY_LEN_MEMORY_ID: MemoryId = MemoryId::new(24);
+// MemoryId 25 held the single feed breaker state
+pub(crate) const BREAKER_EVENTS_MEMORY_ID: MemoryId = MemoryId::new(26);
+pub(crate) const TOKEN_PRICES_MEMORY_ID: MemoryId = MemoryId::new(27);
+pub(crate) const QUARANTINES_MEMORY_ID: MemoryId = MemoryId::new(28);
+pub(crate) const FLASH_LOAN_DEFAULTS_MEMORY_ID: MemoryId = MemoryId::new(29);
 
 // ===== Type Definitions ===== //
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
-// LoanInfo must implement Storable + BoundedStorable
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Asset {
+    CkBTC,
+    CkUSDT,
+}
+
+impl Asset {
+    pub const ALL: [Asset; 2] = [Asset::CkBTC, Asset::CkUSDT];
+
+    /// Symbol of the price feed valuing the asset
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Asset::CkBTC => "ckBTC",
+            Asset::CkUSDT => "ckUSDT",
+        }
+    }
+
+    pub fn decimals(&self) -> u32 {
+        match self {
+            Asset::CkBTC => 8,
+            Asset::CkUSDT => 6,
+        }
+    }
+
+    fn ledger_id(&self) -> Principal {
+        LEDGER_IDS
+            .with(|l| l.borrow().get(self))
+            .unwrap_or_else(|| {
+                let id = match self {
+                    Asset::CkBTC => CKTESTBTC_CANISTER_ID,
+                    Asset::CkUSDT => CKUSDT_CANISTER_ID,
+                };
+                Principal::from_text(id).unwrap()
+            })
+    }
+}
+
+#[derive(CandidType, Deserialize, Default, Clone)]
+struct Loan {
+    collateral: u64,
+    // Debt divided by the borrow index at the time it was taken
+    scaled_debt: u128,
+}
+
+// Loan kept per principal before positions were introduced. Loans written before
+// interest accrued hold `debt`, later ones hold `scaled_debt` instead.
+#[derive(CandidType, Deserialize)]
+struct LegacyLoan {
+    collateral: u64,
+    debt: Option<u64>,
+    scaled_debt: Option<u128>,
+}
+
+// Position ids are unique across all owners so a position keeps its id when it changes hands
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct PositionKey {
+    owner: Principal,
+    position_id: u64,
+}
+
+// Loan as seen by clients, with interest accrued up to now
 #[derive(CandidType, Deserialize, Default, Clone)]
 struct LoanInfo {
+    position_id: u64,
     collateral: u64,
     debt: u64,
 }
 
-#[derive(CandidType, Deserialize, Default, Clone)]
-struct LTVInfo {
-    numerator: u64,
-    denominator: u64,
+#[derive(CandidType, Deserialize, Default, Clone, Debug)]
+pub struct LTVInfo {
+    pub numerator: u64,
+    pub denominator: u64,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -64,7 +220,7 @@ pub enum TransferResult {
     Err(TransferError),
 }
 
-#[derive(CandidType, Deserialize, Debug)]
+#[derive(CandidType, Deserialize, Clone, Debug)]
 pub enum TransferError {
     GenericError {
         message: String,
@@ -89,106 +245,670 @@ pub enum TransferError {
     },
 }
 
-// Implement Storable manually
-impl Storable for LoanInfo {
-    const BOUND: ic_stable_structures::storable::Bound =
-        ic_stable_structures::storable::Bound::Unbounded;
+#[derive(Debug, Serialize, Deserialize, CandidType)]
+struct AllowanceArgs {
+    account: Account,
+    spender: Account,
+}
 
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        candid::encode_one(self).unwrap().into()
-    }
+#[derive(CandidType, Deserialize, Debug)]
+struct Allowance {
+    allowance: Nat,
+    expires_at: Option<u64>,
+}
 
-    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
-    }
+#[derive(Debug, Serialize, Deserialize, CandidType)]
+struct TransferFromArgs {
+    spender_subaccount: Option<Vec<u8>>,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub enum TransferFromResult {
+    #[serde(rename = "Ok")]
+    Ok(Nat),
+
+    #[serde(rename = "Err")]
+    Err(TransferFromError),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TransferFromError {
+    GenericError {
+        message: String,
+        error_code: candid::Nat,
+    },
+    TemporarilyUnavailable,
+    InsufficientAllowance {
+        allowance: candid::Nat,
+    },
+    BadBurn {
+        min_burn_amount: candid::Nat,
+    },
+    Duplicate {
+        duplicate_of: candid::Nat,
+    },
+    BadFee {
+        expected_fee: candid::Nat,
+    },
+    CreatedInFuture {
+        ledger_time: u64,
+    },
+    TooOld,
+    InsufficientFunds {
+        balance: candid::Nat,
+    },
 }
 
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
         RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
 
-    static LOANS: RefCell<StableBTreeMap<Principal, LoanInfo, Memory>> = RefCell::new(
+    static LOANS: RefCell<StableBTreeMap<PositionKey, Loan, Memory>> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0)))
+            MEMORY_MANAGER.with(|m| m.borrow().get(LOANS_MEMORY_ID))
         )
     );
+
+    static NEXT_POSITION_ID: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(memory(NEXT_POSITION_ID_MEMORY_ID), 0)
+            .expect("Failed to init position counter")
+    );
+
+    // Ledgers changed through governance, the constants above are the defaults
+    static LEDGER_IDS: RefCell<StableBTreeMap<Asset, Principal, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(LEDGER_IDS_MEMORY_ID))
+    );
 }
 
-// ===== Canister Methods ===== //
-#[update]
-fn deposit(amount: u64) {
-    let user = ic_cdk::api::msg_caller();
+impl_candid_storable!(Asset, Loan, PositionKey, LegacyLoan);
+
+pub(crate) fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+pub(crate) fn set_ledger_id(asset: Asset, ledger_id: Principal) {
+    LEDGER_IDS.with(|l| l.borrow_mut().insert(asset, ledger_id));
+}
+
+// Apply `f` to an open position and save it if `f` succeeds
+fn update_loan<R>(
+    key: PositionKey,
+    f: impl FnOnce(&mut Loan) -> Result<R, LendingError>,
+) -> Result<R, LendingError> {
     LOANS.with(|loans| {
         let mut map = loans.borrow_mut();
-        let mut entry = map.get(&user).unwrap_or_default();
-        entry.collateral += amount;
-        map.insert(user, entry);
+        let mut entry = map.get(&key).ok_or(LendingError::NoPosition)?;
+        let result = f(&mut entry)?;
+        map.insert(key, entry);
+        Ok(result)
+    })
+}
+
+fn loan_info(position_id: u64, loan: &Loan, index: u128) -> LoanInfo {
+    LoanInfo {
+        position_id,
+        collateral: loan.collateral,
+        debt: interest::to_debt(loan.scaled_debt, index),
+    }
+}
+
+fn next_position_id() -> u64 {
+    NEXT_POSITION_ID.with(|n| {
+        let mut cell = n.borrow_mut();
+        let id = *cell.get();
+        cell.set(id + 1).expect("Failed to save position counter");
+        id
+    })
+}
+
+// Open one position per legacy loan so existing users keep their collateral and debt
+fn migrate_legacy_loans() {
+    let mut legacy: StableBTreeMap<Principal, LegacyLoan, Memory> =
+        StableBTreeMap::init(memory(LEGACY_LOANS_MEMORY_ID));
+    if legacy.is_empty() {
+        return;
+    }
+    let index = interest::accrue(DEBT_ASSET);
+    for (owner, loan) in legacy.iter() {
+        let position_id = next_position_id();
+        let scaled_debt = loan
+            .scaled_debt
+            .unwrap_or_else(|| interest::to_scaled(loan.debt.unwrap_or(0), index));
+        LOANS.with(|loans| {
+            loans.borrow_mut().insert(
+                PositionKey { owner, position_id },
+                Loan {
+                    collateral: loan.collateral,
+                    scaled_debt,
+                },
+            )
+        });
+    }
+    recount_totals();
+    ic_cdk::println!("Migrated {} legacy loans into positions", legacy.len());
+    legacy.clear_new();
+}
+
+// Depending on when the legacy loans were written the totals may already include them,
+// so they are replaced with the sums over all positions
+fn recount_totals() {
+    let (collateral, scaled_debt) = LOANS.with(|loans| {
+        loans
+            .borrow()
+            .iter()
+            .fold((0, 0), |(collateral, scaled_debt), (_, loan)| {
+                (collateral + loan.collateral, scaled_debt + loan.scaled_debt)
+            })
     });
+    caps::update_total_collateral(
+        COLLATERAL_ASSET,
+        collateral,
+        caps::total_collateral(COLLATERAL_ASSET),
+    );
+    interest::update_total_scaled_debt(
+        DEBT_ASSET,
+        scaled_debt,
+        interest::current(DEBT_ASSET).total_scaled_debt,
+    );
+}
+
+// ===== Ledger Helpers ===== //
+// Fee the ledger charges on top of every transfer
+async fn icrc1_fee(asset: Asset) -> Result<Nat, LendingError> {
+    Call::unbounded_wait(asset.ledger_id(), "icrc1_fee")
+        .await
+        .map_err(|e| LendingError::CallFailed(e.to_string()))?
+        .candid()
+        .map_err(|e| LendingError::CallFailed(e.to_string()))
+}
+
+// The ledger fee is paid out of `amount`, so the canister spends exactly what it books
+async fn icrc1_transfer(asset: Asset, to: Account, amount: Nat) -> Result<Nat, LendingError> {
+    let ledger = asset.ledger_id();
+    let fee = icrc1_fee(asset).await?;
+    if amount <= fee {
+        return Err(LendingError::AmountBelowLedgerFee { fee });
+    }
+
+    let transfer_args = TransferArg {
+        from_subaccount: None,
+        to,
+        amount: amount - fee.clone(),
+        fee: Some(fee),
+        memo: None,
+        created_at_time: None,
+    };
+
+    let res: TransferResult = Call::unbounded_wait(ledger, "icrc1_transfer")
+        .with_arg(transfer_args)
+        .await
+        .map_err(|e| LendingError::CallFailed(e.to_string()))?
+        .candid()
+        .map_err(|e| LendingError::CallFailed(e.to_string()))?;
+
+    match res {
+        TransferResult::Ok(block_index) => Ok(block_index),
+        TransferResult::Err(err) => Err(LendingError::Ledger(err)),
+    }
+}
+
+fn canister_account() -> Account {
+    Account {
+        owner: ic_cdk::api::canister_self(),
+        subaccount: None,
+    }
+}
+
+// How much `owner` allows this canister to pull
+async fn icrc2_allowance(asset: Asset, owner: Account) -> Result<Nat, LendingError> {
+    let args = AllowanceArgs {
+        account: owner,
+        spender: canister_account(),
+    };
+    let allowance: Allowance = Call::unbounded_wait(asset.ledger_id(), "icrc2_allowance")
+        .with_arg(args)
+        .await
+        .map_err(|e| LendingError::CallFailed(e.to_string()))?
+        .candid()
+        .map_err(|e| LendingError::CallFailed(e.to_string()))?;
+    Ok(allowance.allowance)
+}
+
+// Pull `amount` from `from` into this canister using its ICRC-2 approval
+async fn icrc2_transfer_from(
+    asset: Asset,
+    from: Account,
+    amount: Nat,
+) -> Result<Nat, LendingError> {
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from,
+        to: canister_account(),
+        amount,
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    let res: TransferFromResult = Call::unbounded_wait(asset.ledger_id(), "icrc2_transfer_from")
+        .with_arg(args)
+        .await
+        .map_err(|e| LendingError::CallFailed(e.to_string()))?
+        .candid()
+        .map_err(|e| LendingError::CallFailed(e.to_string()))?;
+
+    match res {
+        TransferFromResult::Ok(block_index) => Ok(block_index),
+        TransferFromResult::Err(err) => Err(LendingError::LedgerTransferFrom(err)),
+    }
+}
+
+// ===== Lifecycle ===== //
+#[init]
+fn init() {
+    price_refresh::schedule();
 }
 
+#[post_upgrade]
+fn post_upgrade() {
+    migrate_legacy_loans();
+    price_refresh::schedule();
+}
+
+// ===== Canister Methods ===== //
 #[update]
-fn withdraw(amount: u64) {
-    let user = ic_cdk::api::msg_caller();
-    let ltv = get_ltv();
+fn open_position() -> Result<u64, LendingError> {
+    let owner = ic_cdk::api::msg_caller();
+    let position_id = next_position_id();
     LOANS.with(|loans| {
-        let mut map = loans.borrow_mut();
-        let mut entry = map.get(&user).unwrap_or_default();
-        assert!(
-            entry.collateral >= amount,
-            "Insufficient collateral to withdraw"
-        );
-        let max_borrow = (entry.collateral - amount) * ltv.numerator / ltv.denominator;
-        assert!(
-            entry.debt <= max_borrow,
-            "Cannot withdraw while having debt more than 50% of collateral"
-        );
-        entry.collateral -= amount;
-        map.insert(user, entry);
+        loans
+            .borrow_mut()
+            .insert(PositionKey { owner, position_id }, Loan::default())
     });
+    Ok(position_id)
 }
 
+// Only debt-free positions can be closed, their remaining collateral is released
 #[update]
-fn borrow(amount: u64) {
-    let user = ic_cdk::api::msg_caller();
-    let ltv = get_ltv();
-    LOANS.with(|loans| {
-        let mut map = loans.borrow_mut();
-        let mut entry = map.get(&user).unwrap_or_default();
-        let max_borrow = entry.collateral * ltv.numerator / ltv.denominator;
-        assert!(
-            amount <= max_borrow - entry.debt,
-            "Borrow amount exceeds limit"
-        );
-        entry.debt += amount;
-        map.insert(user, entry);
-    });
+fn close_position(position_id: u64) -> Result<(), LendingError> {
+    let key = PositionKey {
+        owner: ic_cdk::api::msg_caller(),
+        position_id,
+    };
+    let index = interest::accrue(DEBT_ASSET);
+    let loan = LOANS
+        .with(|loans| loans.borrow().get(&key))
+        .ok_or(LendingError::NoPosition)?;
+    let debt = interest::to_debt(loan.scaled_debt, index);
+    if debt > 0 {
+        return Err(LendingError::PositionHasDebt { debt });
+    }
+    if loan.collateral > 0 {
+        pause::ensure_active(Operation::Withdraw)?;
+    }
+    LOANS.with(|loans| loans.borrow_mut().remove(&key));
+    position_transfer::clear(position_id);
+    delegation::clear(position_id);
+    caps::update_total_collateral(COLLATERAL_ASSET, 0, loan.collateral);
+    Ok(())
 }
 
 #[update]
-fn repay(amount: u64) {
-    let user = ic_cdk::api::msg_caller();
-    LOANS.with(|loans| {
-        let mut map = loans.borrow_mut();
-        if let Some(mut entry) = map.get(&user) {
-            assert!(entry.debt >= amount, "Repay amount exceeds current debt");
-            entry.debt -= amount;
-            map.insert(user, entry);
+fn deposit(position_id: u64, amount: u64) -> Result<(), LendingError> {
+    pause::ensure_active(Operation::Deposit)?;
+    if amount == 0 {
+        return Err(LendingError::InvalidAmount);
+    }
+    let key = delegation::authorize(position_id, Operation::Deposit, amount)?;
+    caps::ensure_collateral_cap(COLLATERAL_ASSET, amount)?;
+    update_loan(key, |entry| {
+        entry.collateral += amount;
+        Ok(())
+    })?;
+    caps::update_total_collateral(COLLATERAL_ASSET, amount, 0);
+    Ok(())
+}
+
+#[update]
+fn withdraw(position_id: u64, amount: u64) -> Result<(), LendingError> {
+    pause::ensure_active(Operation::Withdraw)?;
+    if amount == 0 {
+        return Err(LendingError::InvalidAmount);
+    }
+    let key = delegation::authorize(position_id, Operation::Withdraw, amount)?;
+    let ltv = risk::risk_params().ltv;
+    let index = interest::accrue(DEBT_ASSET);
+    update_loan(key, |entry| {
+        if entry.collateral < amount {
+            return Err(LendingError::InsufficientCollateral {
+                available: entry.collateral,
+            });
+        }
+        let debt = interest::to_debt(entry.scaled_debt, index);
+        // Debt-free positions can withdraw without a price
+        if debt > 0 {
+            let price = oracle::collateral_price()?;
+            let remaining = oracle::collateral_value(entry.collateral - amount, price);
+            let max_borrow = remaining * ltv.numerator / ltv.denominator;
+            if debt > max_borrow {
+                // Collateral that has to stay locked for the current debt
+                let locked =
+                    oracle::collateral_for((debt * ltv.denominator).div_ceil(ltv.numerator), price);
+                return Err(LendingError::WithdrawLimitExceeded {
+                    available: entry.collateral.saturating_sub(locked),
+                });
+            }
         }
+        entry.collateral -= amount;
+        Ok(())
+    })?;
+    caps::update_total_collateral(COLLATERAL_ASSET, 0, amount);
+    Ok(())
+}
+
+// The loan is sent to the caller, minus the ledger fee
+#[update]
+async fn borrow(position_id: u64, amount: u64) -> Result<(), LendingError> {
+    pause::ensure_active(Operation::Borrow)?;
+    price_breaker::ensure_clear()?;
+    if amount == 0 {
+        return Err(LendingError::InvalidAmount);
+    }
+    let key = delegation::authorize(position_id, Operation::Borrow, amount)?;
+    let ltv = risk::risk_params().ltv;
+    let price = oracle::collateral_price()?;
+    let index = interest::accrue(DEBT_ASSET);
+    let scaled = interest::to_scaled(amount, index);
+    let available = pool::available_liquidity(DEBT_ASSET);
+    if amount > available {
+        return Err(LendingError::InsufficientLiquidity { available });
+    }
+    caps::ensure_borrow_cap(DEBT_ASSET, amount)?;
+    // Record the debt before the transfer so concurrent calls cannot borrow the same liquidity
+    update_loan(key, |entry| {
+        let value = oracle::collateral_value(entry.collateral, price);
+        let max_borrow = value * ltv.numerator / ltv.denominator;
+        let debt = interest::to_debt(entry.scaled_debt, index);
+        let available = max_borrow.saturating_sub(debt);
+        if amount > available {
+            return Err(LendingError::BorrowLimitExceeded { available });
+        }
+        entry.scaled_debt += scaled;
+        Ok(())
+    })?;
+    interest::update_total_scaled_debt(DEBT_ASSET, scaled, 0);
+    delegation::record_borrow(position_id, amount);
+
+    let to = Account {
+        owner: ic_cdk::api::msg_caller(),
+        subaccount: None,
+    };
+    if let Err(e) = icrc1_transfer(DEBT_ASSET, to, Nat::from(amount)).await {
+        // Nothing was paid out, take the debt back off the position
+        interest::accrue(DEBT_ASSET);
+        if let Ok(removed) = update_loan(key, |entry| {
+            let removed = scaled.min(entry.scaled_debt);
+            entry.scaled_debt -= removed;
+            Ok(removed)
+        }) {
+            interest::update_total_scaled_debt(DEBT_ASSET, 0, removed);
+        }
+        delegation::release_borrow(position_id, amount);
+        return Err(e);
+    }
+    Ok(())
+}
+
+// The caller must approve `amount` plus the ledger fee to this canister first
+#[update]
+async fn repay(position_id: u64, amount: u64) -> Result<(), LendingError> {
+    pause::ensure_active(Operation::Repay)?;
+    if amount == 0 {
+        return Err(LendingError::InvalidAmount);
+    }
+    let key = delegation::authorize(position_id, Operation::Repay, amount)?;
+    let index = interest::accrue(DEBT_ASSET);
+    let loan = LOANS
+        .with(|loans| loans.borrow().get(&key))
+        .ok_or(LendingError::NoPosition)?;
+    let debt = interest::to_debt(loan.scaled_debt, index);
+    if amount > debt {
+        return Err(LendingError::RepayExceedsDebt { debt });
+    }
+
+    // Debt is only reduced for ckUSDT that has arrived
+    let payer = Account {
+        owner: ic_cdk::api::msg_caller(),
+        subaccount: None,
+    };
+    icrc2_transfer_from(DEBT_ASSET, payer.clone(), Nat::from(amount)).await?;
+
+    // The position may have changed while the ledger was called
+    let index = interest::accrue(DEBT_ASSET);
+    let result = update_loan(key, |entry| {
+        let debt = interest::to_debt(entry.scaled_debt, index);
+        if amount > debt {
+            return Err(LendingError::RepayExceedsDebt { debt });
+        }
+        // Repaying the whole debt clears any rounding dust
+        let repaid = if amount == debt {
+            entry.scaled_debt
+        } else {
+            (amount as u128 * interest::RAY / index).min(entry.scaled_debt)
+        };
+        entry.scaled_debt -= repaid;
+        Ok(repaid)
     });
+    match result {
+        Ok(repaid) => {
+            interest::update_total_scaled_debt(DEBT_ASSET, 0, repaid);
+            Ok(())
+        }
+        Err(e) => {
+            refund(payer, amount).await;
+            Err(e)
+        }
+    }
 }
 
 #[query]
 fn get_balances() -> Vec<(Principal, LoanInfo)> {
-    LOANS.with(|loans| loans.borrow().iter().collect())
+    let index = interest::current(DEBT_ASSET).index;
+    LOANS.with(|loans| {
+        loans
+            .borrow()
+            .iter()
+            .map(|(key, loan)| (key.owner, loan_info(key.position_id, &loan, index)))
+            .collect()
+    })
+}
+
+#[query]
+fn list_my_positions() -> Vec<LoanInfo> {
+    let owner = ic_cdk::api::msg_caller();
+    let index = interest::current(DEBT_ASSET).index;
+    let first = PositionKey {
+        owner,
+        position_id: 0,
+    };
+    LOANS.with(|loans| {
+        loans
+            .borrow()
+            .range(first..)
+            .take_while(|(key, _)| key.owner == owner)
+            .map(|(key, loan)| loan_info(key.position_id, &loan, index))
+            .collect()
+    })
+}
+
+#[query]
+fn get_ledger_ids() -> Vec<(Asset, Principal)> {
+    Asset::ALL
+        .iter()
+        .map(|asset| (*asset, asset.ledger_id()))
+        .collect()
 }
 
 #[query]
 fn get_ltv() -> LTVInfo {
-    // 50% LTV
-    return LTVInfo {
-        numerator: 50,
-        denominator: 100,
+    risk::risk_params().ltv
+}
+
+// Debt repaid and collateral seized when liquidating a position
+struct Liquidation {
+    // Scaled debt taken off the position
+    scaled: u128,
+    debt: u64,
+    repaid: u64,
+    // Debt the collateral is not worth, it becomes bad debt
+    deficit: u64,
+    seized: u64,
+    // Part of the penalty kept by the treasury, the liquidator receives the rest of `seized`
+    protocol_fee: u64,
+}
+
+// Liquidation of `loan`, covering at most `max_repaid` of its debt
+fn liquidation(
+    loan: &Loan,
+    params: &risk::RiskParams,
+    price: Price,
+    index: u128,
+    max_repaid: u64,
+) -> Result<Liquidation, LendingError> {
+    let threshold = &params.liquidation_threshold;
+    let debt = interest::to_debt(loan.scaled_debt, index);
+    let value = oracle::collateral_value(loan.collateral, price);
+    let max_debt = value * threshold.numerator / threshold.denominator;
+    if debt <= max_debt {
+        return Err(LendingError::NotLiquidatable);
+    }
+
+    // Seize collateral up to the repaid debt plus the penalty, the rest stays with the user
+    let deficit = debt.saturating_sub(value);
+    let repaid = value.min(debt).min(max_repaid);
+    // Covered debt the liquidator did not repay stays on the position
+    let remaining = debt - deficit - repaid;
+    let scaled = if remaining == 0 {
+        loan.scaled_debt
+    } else {
+        loan.scaled_debt
+            .saturating_sub(interest::to_scaled(remaining, index))
+    };
+    let repaid_collateral = oracle::collateral_for(repaid, price).min(loan.collateral);
+    let penalty = (loan.collateral - repaid_collateral).min(oracle::collateral_for(
+        params.liquidation_penalty(repaid),
+        price,
+    ));
+    Ok(Liquidation {
+        scaled,
+        debt,
+        repaid,
+        deficit,
+        seized: repaid_collateral + penalty,
+        protocol_fee: params.liquidation_protocol_fee(penalty),
+    })
+}
+
+fn ensure_position(key: &PositionKey) -> Result<(), LendingError> {
+    if LOANS.with(|loans| loans.borrow().contains_key(key)) {
+        Ok(())
+    } else {
+        Err(LendingError::NoPosition)
+    }
+}
+
+// Return ckUSDT pulled from the caller that ended up not being needed
+async fn refund(to: Account, amount: u64) {
+    if amount == 0 {
+        return;
+    }
+    if let Err(e) = icrc1_transfer(DEBT_ASSET, to.clone(), Nat::from(amount)).await {
+        ic_cdk::println!("Refund of {} to {} failed: {:?}", amount, to.owner, e);
+    }
+}
+
+// The liquidator repays the covered debt, it must approve it plus the ledger fee first.
+// The seized collateral minus the protocol fee goes to `receiver_position_id`, a position
+// the liquidator owns.
+#[update]
+async fn liquidate(
+    owner: Principal,
+    position_id: u64,
+    receiver_position_id: u64,
+) -> Result<(), LendingError> {
+    pause::ensure_active(Operation::Liquidate)?;
+    let params = risk::risk_params();
+    let price = oracle::collateral_price()?;
+    let index = interest::accrue(DEBT_ASSET);
+    let key = PositionKey { owner, position_id };
+    let receiver = PositionKey {
+        owner: ic_cdk::api::msg_caller(),
+        position_id: receiver_position_id,
+    };
+    ensure_position(&receiver)?;
+    let loan = LOANS
+        .with(|loans| loans.borrow().get(&key))
+        .ok_or(LendingError::NoPosition)?;
+    let quote = liquidation(&loan, &params, price, index, u64::MAX)?;
+
+    let liquidator = Account {
+        owner: receiver.owner,
+        subaccount: None,
+    };
+    // Worthless collateral leaves nothing to repay, the whole debt becomes bad debt
+    if quote.repaid > 0 {
+        icrc2_transfer_from(DEBT_ASSET, liquidator.clone(), Nat::from(quote.repaid)).await?;
+    }
+
+    // The positions may have changed while the ledger was called
+    let index = interest::accrue(DEBT_ASSET);
+    let result = ensure_position(&receiver).and_then(|()| {
+        update_loan(key, |entry| {
+            let liquidation = liquidation(entry, &params, price, index, quote.repaid)?;
+            entry.collateral -= liquidation.seized;
+            entry.scaled_debt -= liquidation.scaled;
+            Ok(liquidation)
+        })
+    });
+    let liquidation = match result {
+        Ok(liquidation) => liquidation,
+        Err(e) => {
+            refund(liquidator, quote.repaid).await;
+            return Err(e);
+        }
     };
+    update_loan(receiver, |entry| {
+        entry.collateral += liquidation.seized - liquidation.protocol_fee;
+        Ok(())
+    })
+    .expect("Receiving position was checked above");
+    interest::update_total_scaled_debt(DEBT_ASSET, 0, liquidation.scaled);
+    caps::update_total_collateral(COLLATERAL_ASSET, 0, liquidation.protocol_fee);
+    treasury::record_liquidation_penalty(COLLATERAL_ASSET, liquidation.protocol_fee);
+
+    ic_cdk::println!(
+        "Position {} of {} has been liquidated: debt {}, collateral seized {}",
+        position_id,
+        owner.to_text(),
+        liquidation.debt,
+        liquidation.seized
+    );
+
+    if liquidation.deficit > 0 {
+        bad_debt::record_bad_debt(
+            owner,
+            liquidation.debt,
+            liquidation.seized,
+            liquidation.deficit,
+        );
+    }
+    refund(liquidator, quote.repaid - liquidation.repaid).await;
+    Ok(())
 }
 
 /*
@@ -244,36 +964,89 @@ async fn withdraw_cktestbtc(to: Principal, amount: Nat) -> Result<Nat, String> {
         TransferResult::Err(err) => Err(format!("Transfer error: {:?}", err)),
     }
 }
-
-#[update]
-fn set_price(price: u64) {
-    crate::oracle::set_token_price(price);
-}
-
-#[query]
-fn get_price() -> u64 {
-    get_token_price()
-}
-
-#[update]
-fn liquidate(principal: Principal) {
-    let price = get_price();
-    COLLATERALS.with(|coll| {
-        BORROWS.with(|debt| {
-            let mut coll = coll.borrow_mut();
-            let mut debt = debt.borrow_mut();
-            let user_coll = coll.get(&principal).copied().unwrap_or(0);
-            let user_debt = debt.get(&principal).copied().unwrap_or(0);
-            let max_borrow = user_coll * 60 / 100;
-            if user_debt > max_borrow {
-                coll.remove(&principal);
-                debt.remove(&principal);
-                ic_cdk::println!("User {} has been liquidated", principal.to_text());
-            }
-        });
-    });
-}
 */
 
 //Export Candid
 export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // $60,000 per BTC, ckBTC has 8 decimals and ckUSDT 6
+    const PRICE: Price = Price {
+        mantissa: 6_000_000_000_000,
+        decimals: Price::DECIMALS,
+    };
+    const ONE_BTC: u64 = 100_000_000;
+    const DOLLAR: u64 = 1_000_000;
+
+    fn loan(collateral: u64, debt: u64) -> Loan {
+        Loan {
+            collateral,
+            scaled_debt: interest::to_scaled(debt, interest::RAY),
+        }
+    }
+
+    fn liquidate(loan: &Loan, price: Price) -> Result<Liquidation, LendingError> {
+        liquidation(loan, &RiskParams::default(), price, interest::RAY, u64::MAX)
+    }
+
+    #[test]
+    fn healthy_positions_cannot_be_liquidated() {
+        // The default threshold is 60%
+        assert!(matches!(
+            liquidate(&loan(ONE_BTC, 36_000 * DOLLAR), PRICE),
+            Err(LendingError::NotLiquidatable)
+        ));
+        assert!(liquidate(&loan(ONE_BTC, 36_001 * DOLLAR), PRICE).is_ok());
+    }
+
+    #[test]
+    fn covered_debt_is_repaid_with_a_penalty() {
+        let loan = loan(ONE_BTC, 40_000 * DOLLAR);
+        let liquidation = liquidate(&loan, PRICE).unwrap();
+        assert_eq!(liquidation.debt, 40_000 * DOLLAR);
+        assert_eq!(liquidation.repaid, 40_000 * DOLLAR);
+        assert_eq!(liquidation.deficit, 0);
+        assert_eq!(liquidation.scaled, loan.scaled_debt);
+        // 2/3 BTC for the debt, rounded up, plus a 5% penalty
+        assert_eq!(liquidation.seized, 66_666_667 + 3_333_334);
+        // 10% of the penalty goes to the treasury
+        assert_eq!(liquidation.protocol_fee, 333_333);
+    }
+
+    #[test]
+    fn unrepaid_covered_debt_stays_on_the_position() {
+        let loan = loan(ONE_BTC, 40_000 * DOLLAR);
+        let params = RiskParams::default();
+        let liquidation =
+            liquidation(&loan, &params, PRICE, interest::RAY, 30_000 * DOLLAR).unwrap();
+        assert_eq!(liquidation.repaid, 30_000 * DOLLAR);
+        assert_eq!(liquidation.deficit, 0);
+        let left = loan.scaled_debt - liquidation.scaled;
+        assert_eq!(interest::to_debt(left, interest::RAY), 10_000 * DOLLAR);
+    }
+
+    #[test]
+    fn underwater_positions_repay_what_the_collateral_is_worth() {
+        let liquidation = liquidate(&loan(ONE_BTC, 70_000 * DOLLAR), PRICE).unwrap();
+        assert_eq!(liquidation.debt, 70_000 * DOLLAR);
+        assert_eq!(liquidation.repaid, 60_000 * DOLLAR);
+        assert_eq!(liquidation.deficit, 10_000 * DOLLAR);
+        // Nothing is left to pay the penalty with
+        assert_eq!(liquidation.seized, ONE_BTC);
+        assert_eq!(liquidation.protocol_fee, 0);
+    }
+
+    #[test]
+    fn worthless_collateral_repays_nothing() {
+        // 1,000 sats at one cent are worth less than one ckUSDT unit
+        let price = Price::new(1_000_000);
+        let liquidation = liquidate(&loan(1_000, 10 * DOLLAR), price).unwrap();
+        assert_eq!(liquidation.repaid, 0);
+        assert_eq!(liquidation.deficit, 10 * DOLLAR);
+        assert_eq!(liquidation.seized, 0);
+        assert_eq!(liquidation.protocol_fee, 0);
+    }
+}
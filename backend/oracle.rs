@@ -1,22 +1,690 @@
-
+use candid::{CandidType, Deserialize};
+use ic_cdk::management_canister::{
+    http_request, transform_context_from_query, HttpHeader, HttpMethod, HttpRequestArgs,
+    HttpRequestResult, TransformArgs,
+};
+use ic_cdk_macros::query;
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use serde_json::Value;
 use std::cell::RefCell;
-pub use get_token_price as get_price;
+
+use crate::error::LendingError;
+use crate::price::Price;
+use crate::xrc::{self, XrcSource};
+use crate::{memory, Memory, ORACLE_CONFIG_MEMORY_ID, TOKEN_PRICES_MEMORY_ID};
+use crate::{price_breaker, price_history};
+use crate::{Asset, BPS, COLLATERAL_ASSET, DEBT_ASSET};
+
+// Name recorded for quotes of the exchange rate canister
+const XRC_SOURCE: &str = "xrc";
+
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+const DEFAULT_MAX_PRICE_AGE: u64 = 60 * NANOS_PER_MINUTE;
+const DEFAULT_REFRESH_INTERVAL: u64 = 5 * NANOS_PER_MINUTE;
+const MAX_TWAP_WINDOW: u64 = 24 * 60 * NANOS_PER_MINUTE;
+const DEFAULT_PRICE_JUMP_WINDOW: u64 = 60 * NANOS_PER_MINUTE;
+
+/// HTTP endpoint quoting the USD price of an asset
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PriceSource {
+    pub name: String,
+    pub url: String,
+    /// Dot-separated path of the price in the JSON response, array items are selected by index
+    pub json_path: String,
+}
+
+impl PriceSource {
+    fn new(name: &str, url: &str, json_path: &str) -> Self {
+        PriceSource {
+            name: name.to_string(),
+            url: url.to_string(),
+            json_path: json_path.to_string(),
+        }
+    }
+}
+
+/// Sources of the USD price of one asset
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FeedConfig {
+    pub symbol: String,
+    pub sources: Vec<PriceSource>,
+    /// Exchange rate canister queried next to the HTTP sources
+    pub xrc: Option<XrcSource>,
+    /// Overrides the default refresh interval for this feed
+    pub refresh_interval: Option<u64>,
+    /// Overrides the default minimum sources, e.g. 1 for a feed read from the XRC alone
+    pub min_sources: Option<u32>,
+}
+
+impl FeedConfig {
+    fn new(symbol: &str, sources: Vec<PriceSource>) -> Self {
+        FeedConfig {
+            symbol: symbol.to_string(),
+            sources,
+            xrc: None,
+            refresh_interval: None,
+            min_sources: None,
+        }
+    }
+
+    fn source_count(&self) -> usize {
+        self.sources.len() + self.xrc.iter().count()
+    }
+}
+
+/// Where prices come from and how much the sources of a feed have to agree
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct OracleConfig {
+    pub feeds: Vec<FeedConfig>,
+    /// Quotes further than this from the median are discarded
+    pub max_deviation_bps: u64,
+    /// Quotes that have to remain for a price to be accepted, unless the feed overrides it
+    pub min_sources: u32,
+    /// Age in nanoseconds after which the price is too old to borrow, withdraw or liquidate against
+    pub max_price_age: u64,
+    /// Nanoseconds between scheduled refreshes of a feed while they succeed
+    pub refresh_interval: u64,
+    /// Value positions at the time-weighted average over this many nanoseconds instead of spot
+    pub twap_window: Option<u64>,
+    /// Largest move from the last accepted price that is accepted without confirmation
+    pub max_price_jump_bps: u64,
+    /// Only prices accepted within this many nanoseconds are compared against
+    pub price_jump_window: u64,
+    /// Agreeing readings that lift a quarantined price
+    pub breaker_confirmations: u32,
+}
+
+impl Default for OracleConfig {
+    fn default() -> Self {
+        OracleConfig {
+            feeds: vec![
+                FeedConfig::new(
+                    "ckBTC",
+                    vec![
+                        PriceSource::new(
+                            "coingecko",
+                            "https://api.coingecko.com/api/v3/simple/price?ids=chain-key-bitcoin&vs_currencies=usd",
+                            "chain-key-bitcoin.usd",
+                        ),
+                        PriceSource::new(
+                            "coinbase",
+                            "https://api.coinbase.com/v2/prices/BTC-USD/spot",
+                            "data.amount",
+                        ),
+                        PriceSource::new(
+                            "kraken",
+                            "https://api.kraken.com/0/public/Ticker?pair=XBTUSD",
+                            "result.XXBTZUSD.c.0",
+                        ),
+                    ],
+                ),
+                FeedConfig::new(
+                    "ckETH",
+                    vec![
+                        PriceSource::new(
+                            "coingecko",
+                            "https://api.coingecko.com/api/v3/simple/price?ids=ethereum&vs_currencies=usd",
+                            "ethereum.usd",
+                        ),
+                        PriceSource::new(
+                            "coinbase",
+                            "https://api.coinbase.com/v2/prices/ETH-USD/spot",
+                            "data.amount",
+                        ),
+                        PriceSource::new(
+                            "kraken",
+                            "https://api.kraken.com/0/public/Ticker?pair=ETHUSD",
+                            "result.XETHZUSD.c.0",
+                        ),
+                    ],
+                ),
+                FeedConfig::new(
+                    "ICP",
+                    vec![
+                        PriceSource::new(
+                            "coingecko",
+                            "https://api.coingecko.com/api/v3/simple/price?ids=internet-computer&vs_currencies=usd",
+                            "internet-computer.usd",
+                        ),
+                        PriceSource::new(
+                            "coinbase",
+                            "https://api.coinbase.com/v2/prices/ICP-USD/spot",
+                            "data.amount",
+                        ),
+                        PriceSource::new(
+                            "kraken",
+                            "https://api.kraken.com/0/public/Ticker?pair=ICPUSD",
+                            "result.ICPUSD.c.0",
+                        ),
+                    ],
+                ),
+                FeedConfig::new(
+                    "ckUSDT",
+                    vec![
+                        PriceSource::new(
+                            "coingecko",
+                            "https://api.coingecko.com/api/v3/simple/price?ids=tether&vs_currencies=usd",
+                            "tether.usd",
+                        ),
+                        PriceSource::new(
+                            "coinbase",
+                            "https://api.coinbase.com/v2/prices/USDT-USD/spot",
+                            "data.amount",
+                        ),
+                        PriceSource::new(
+                            "kraken",
+                            "https://api.kraken.com/0/public/Ticker?pair=USDTUSD",
+                            "result.USDTZUSD.c.0",
+                        ),
+                    ],
+                ),
+            ],
+            max_deviation_bps: 200,
+            min_sources: 2,
+            max_price_age: DEFAULT_MAX_PRICE_AGE,
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            twap_window: None,
+            max_price_jump_bps: 1_000,
+            price_jump_window: DEFAULT_PRICE_JUMP_WINDOW,
+            breaker_confirmations: 3,
+        }
+    }
+}
+
+impl OracleConfig {
+    pub fn feed(&self, symbol: &str) -> Option<&FeedConfig> {
+        self.feeds.iter().find(|feed| feed.symbol == symbol)
+    }
+
+    pub fn refresh_interval_of(&self, feed: &FeedConfig) -> u64 {
+        feed.refresh_interval.unwrap_or(self.refresh_interval)
+    }
+
+    pub fn min_sources_of(&self, feed: &FeedConfig) -> u32 {
+        feed.min_sources.unwrap_or(self.min_sources)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for asset in [COLLATERAL_ASSET, DEBT_ASSET] {
+            if self.feed(asset.symbol()).is_none() {
+                return Err(format!("A feed for {} is required", asset.symbol()));
+            }
+        }
+        for (i, feed) in self.feeds.iter().enumerate() {
+            if self.feeds[..i].iter().any(|f| f.symbol == feed.symbol) {
+                return Err(format!("Duplicate feed {}", feed.symbol));
+            }
+            for (j, source) in feed.sources.iter().enumerate() {
+                if !source.url.starts_with("https://") {
+                    return Err(format!("Price source {} must use https", source.name));
+                }
+                if source.name == XRC_SOURCE
+                    || feed.sources[..j].iter().any(|s| s.name == source.name)
+                {
+                    return Err(format!("Duplicate price source {}", source.name));
+                }
+            }
+            let min_sources = self.min_sources_of(feed);
+            if min_sources == 0 || min_sources as usize > feed.source_count() {
+                return Err(format!(
+                    "Minimum sources of {} must be between 1 and its number of sources",
+                    feed.symbol
+                ));
+            }
+            if let Some(interval) = feed.refresh_interval {
+                if interval < NANOS_PER_MINUTE || interval > self.max_price_age {
+                    return Err(format!(
+                        "Refresh interval of {} must be between a minute and the maximum price age",
+                        feed.symbol
+                    ));
+                }
+            }
+        }
+        if self.min_sources == 0 {
+            return Err("At least one source has to agree".to_string());
+        }
+        if self.max_deviation_bps == 0 || self.max_deviation_bps > BPS {
+            return Err("Maximum deviation must be between 0 and 100%".to_string());
+        }
+        if self.max_price_age < NANOS_PER_MINUTE {
+            return Err("Maximum price age must be at least a minute".to_string());
+        }
+        if self.refresh_interval < NANOS_PER_MINUTE || self.refresh_interval > self.max_price_age {
+            return Err(
+                "Refresh interval must be between a minute and the maximum price age".to_string(),
+            );
+        }
+        if let Some(window) = self.twap_window {
+            if !(NANOS_PER_MINUTE..=MAX_TWAP_WINDOW).contains(&window) {
+                return Err("TWAP window must be between a minute and a day".to_string());
+            }
+        }
+        if self.max_price_jump_bps == 0 || self.max_price_jump_bps > BPS {
+            return Err("Maximum price jump must be between 0 and 100%".to_string());
+        }
+        if self.price_jump_window < NANOS_PER_MINUTE {
+            return Err("Price jump window must be at least a minute".to_string());
+        }
+        if self.breaker_confirmations == 0 {
+            return Err("At least one confirmation is required".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Last price accepted for a feed
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct TokenPrice {
+    /// USD per whole token, zero until the first update
+    pub price: Price,
+    pub last_updated: u64,
+    /// Sources whose quotes made up the price
+    pub sources: Vec<String>,
+}
+
+/// Current price with its freshness
+#[derive(CandidType, Deserialize, Clone)]
+pub struct PriceInfo {
+    pub price: Price,
+    pub last_updated: u64,
+    pub sources: Vec<String>,
+    pub max_age: u64,
+    /// Borrowing, withdrawing and liquidating are refused while the price is stale
+    pub stale: bool,
+}
+
+impl_candid_storable!(OracleConfig, TokenPrice);
 
 thread_local! {
-    static TOKEN_PRICE_USD: RefCell<u64> = RefCell::new(100); // Default price: $100
+    static TOKEN_PRICES: RefCell<StableBTreeMap<String, TokenPrice, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(TOKEN_PRICES_MEMORY_ID))
+    );
+
+    static CONFIG: RefCell<StableCell<OracleConfig, Memory>> = RefCell::new(
+        StableCell::init(memory(ORACLE_CONFIG_MEMORY_ID), OracleConfig::default())
+            .expect("Failed to init oracle config")
+    );
+}
+
+pub fn token_price(symbol: &str) -> TokenPrice {
+    TOKEN_PRICES
+        .with(|p| p.borrow().get(&symbol.to_string()))
+        .unwrap_or_default()
+}
+
+pub fn config() -> OracleConfig {
+    CONFIG.with(|c| c.borrow().get().clone())
+}
+
+/// Replace the price sources, only called by governance
+pub fn set_config(config: OracleConfig) {
+    CONFIG.with(|c| {
+        c.borrow_mut()
+            .set(config)
+            .expect("Failed to save oracle config")
+    });
+}
+
+fn is_stale(current: &TokenPrice, max_age: u64) -> bool {
+    current.price.is_zero() || ic_cdk::api::time().saturating_sub(current.last_updated) > max_age
+}
+
+/// USD price of `symbol`, refused once older than the max age.
+/// Spot unless a TWAP window is configured.
+pub fn price(symbol: &str) -> Result<Price, LendingError> {
+    let current = token_price(symbol);
+    let config = config();
+    if is_stale(&current, config.max_price_age) {
+        return Err(LendingError::OracleStale {
+            last_updated: current.last_updated,
+        });
+    }
+    let price = config
+        .twap_window
+        .and_then(|window| price_history::twap(symbol, window, ic_cdk::api::time()))
+        .unwrap_or(current.price);
+    Ok(price)
+}
+
+/// Price of one `base` in `quote`, derived from their USD feeds
+pub fn cross_rate(base: &str, quote: &str) -> Result<Price, LendingError> {
+    let base = price(base)?;
+    let quote = price(quote)?;
+    (base.mantissa as u128 * 10u128.pow(Price::DECIMALS))
+        .checked_div(quote.mantissa as u128)
+        .and_then(|mantissa| Price::from_parts(mantissa, Price::DECIMALS))
+        .filter(|price| !price.is_zero())
+        .ok_or(LendingError::PriceOutOfRange)
+}
+
+/// Price of the collateral in the debt asset, which positions are valued at
+pub fn collateral_price() -> Result<Price, LendingError> {
+    cross_rate(COLLATERAL_ASSET.symbol(), DEBT_ASSET.symbol())
 }
 
-// Getter
-pub fn get_token_price() -> u64 {
-    TOKEN_PRICE_USD.with(|p| *p.borrow())
+/// `amount` of `from` valued in units of `to`
+pub fn convert(amount: u64, from: Asset, to: Asset) -> Result<u64, LendingError> {
+    if from == to {
+        return Ok(amount);
+    }
+    let price = cross_rate(from.symbol(), to.symbol())?;
+    (amount as u128)
+        .checked_mul(price.mantissa as u128)
+        .and_then(|value| value.checked_mul(10u128.pow(to.decimals())))
+        .map(|value| value / 10u128.pow(from.decimals() + price.decimals))
+        .and_then(|value| u64::try_from(value).ok())
+        .ok_or(LendingError::PriceOutOfRange)
 }
 
-// Setter (1)
-pub fn set_token_price(price: u64) {
-    TOKEN_PRICE_USD.with(|p| *p.borrow_mut() = price);
+// Converts collateral units times the price mantissa into debt units
+fn value_scale(price: Price) -> u128 {
+    10u128.pow(COLLATERAL_ASSET.decimals() + price.decimals - DEBT_ASSET.decimals())
 }
 
-// Setter (2) — opsional kalau kamu mau pakai nama berbeda
-pub fn update_token_price(new_price: u64) {
-    TOKEN_PRICE_USD.with(|p| *p.borrow_mut() = new_price);
+/// Value of `amount` collateral in debt-asset units, rounded down
+pub fn collateral_value(amount: u64, price: Price) -> u64 {
+    (amount as u128 * price.mantissa as u128 / value_scale(price)) as u64
+}
+
+/// Collateral worth `value` debt-asset units, rounded up
+pub fn collateral_for(value: u64, price: Price) -> u64 {
+    (value as u128 * value_scale(price)).div_ceil(price.mantissa as u128) as u64
+}
+
+// Price found at `path` in a JSON body
+fn parse_price(body: &[u8], path: &str) -> Option<Price> {
+    let json: Value = serde_json::from_slice(body).ok()?;
+    let quote = path.split('.').try_fold(&json, |value, key| match value {
+        Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+        _ => value.get(key),
+    })?;
+    // Numbers keep their original digits, some exchanges quote prices as strings
+    let price = match quote {
+        Value::Number(number) => Price::parse(&number.to_string()),
+        Value::String(text) => Price::parse(text),
+        _ => None,
+    }?;
+    Some(price).filter(|price| !price.is_zero())
+}
+
+async fn fetch_price(source: &PriceSource) -> Result<Price, LendingError> {
+    let request = HttpRequestArgs {
+        url: source.url.clone(),
+        max_response_bytes: Some(2048),
+        method: HttpMethod::GET,
+        headers: vec![HttpHeader {
+            name: "Accept".to_string(),
+            value: "application/json".to_string(),
+        }],
+        body: None,
+        transform: Some(transform_context_from_query(
+            "transform_price_response".to_string(),
+            source.json_path.as_bytes().to_vec(),
+        )),
+    };
+    let response = http_request(&request)
+        .await
+        .map_err(|e| LendingError::CallFailed(e.to_string()))?;
+    if response.status != 200u16 {
+        return Err(LendingError::CallFailed(format!(
+            "Price source {} returned status {}",
+            source.name, response.status
+        )));
+    }
+
+    // The transform left only the price
+    std::str::from_utf8(&response.body)
+        .ok()
+        .and_then(Price::parse)
+        .filter(|price| !price.is_zero())
+        .ok_or_else(|| LendingError::CallFailed(format!("Malformed response from {}", source.name)))
+}
+
+/// Whether `price` is at most `max_bps` away from `reference`
+pub fn within(price: Price, reference: Price, max_bps: u64) -> bool {
+    price.mantissa.abs_diff(reference.mantissa) as u128 * BPS as u128
+        <= max_bps as u128 * reference.mantissa as u128
+}
+
+fn median(sorted: &[u64]) -> u64 {
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        ((sorted[mid - 1] as u128 + sorted[mid] as u128) / 2) as u64
+    } else {
+        sorted[mid]
+    }
+}
+
+/// Median of the quotes that lie within `max_deviation_bps` of the median of all quotes
+fn aggregate(
+    mut quotes: Vec<(String, Price)>,
+    max_deviation_bps: u64,
+    min_sources: u32,
+) -> Result<(Price, Vec<String>), LendingError> {
+    // Quotes share `Price::DECIMALS`, so mantissas compare directly
+    quotes.sort_by_key(|(_, price)| price.mantissa);
+    let prices: Vec<u64> = quotes.iter().map(|(_, price)| price.mantissa).collect();
+    let center = if prices.is_empty() {
+        0
+    } else {
+        median(&prices)
+    };
+
+    let accepted: Vec<(String, Price)> = quotes
+        .into_iter()
+        .filter(|(_, price)| within(*price, Price::new(center), max_deviation_bps))
+        .collect();
+    if accepted.len() < min_sources as usize {
+        return Err(LendingError::PriceSourcesDisagree {
+            agreeing: accepted.len() as u32,
+            required: min_sources,
+        });
+    }
+
+    let prices: Vec<u64> = accepted.iter().map(|(_, price)| price.mantissa).collect();
+    let sources = accepted.into_iter().map(|(name, _)| name).collect();
+    Ok((Price::new(median(&prices)), sources))
+}
+
+/// Fetch every source of `feed` and store the aggregated price
+pub async fn refresh(feed: &FeedConfig) -> Result<Price, LendingError> {
+    let config = config();
+    let mut quotes = Vec::new();
+    for source in &feed.sources {
+        // A failing source only counts as missing
+        match fetch_price(source).await {
+            Ok(price) => quotes.push((source.name.clone(), price)),
+            Err(err) => ic_cdk::println!("Price source {} failed: {:?}", source.name, err),
+        }
+    }
+    if let Some(source) = &feed.xrc {
+        match xrc::fetch_rate(source).await {
+            Ok(price) => quotes.push((XRC_SOURCE.to_string(), price)),
+            Err(err) => ic_cdk::println!("Price source {} failed: {:?}", XRC_SOURCE, err),
+        }
+    }
+
+    let (price, sources) = aggregate(
+        quotes,
+        config.max_deviation_bps,
+        config.min_sources_of(feed),
+    )?;
+    price_breaker::check(&feed.symbol, price, &sources, &token_price(&feed.symbol))?;
+    store_price(&feed.symbol, price, sources, ic_cdk::api::time());
+    Ok(price)
+}
+
+/// Accept `price` as the current price of `symbol`
+pub fn store_price(symbol: &str, price: Price, sources: Vec<String>, now: u64) {
+    let update = TokenPrice {
+        price,
+        last_updated: now,
+        sources,
+    };
+    TOKEN_PRICES.with(|p| p.borrow_mut().insert(symbol.to_string(), update));
+    price_history::record(symbol, price, now);
+}
+
+fn price_info(symbol: &str, max_age: u64) -> PriceInfo {
+    let current = token_price(symbol);
+    PriceInfo {
+        stale: is_stale(&current, max_age),
+        price: current.price,
+        last_updated: current.last_updated,
+        sources: current.sources,
+        max_age,
+    }
+}
+
+// ===== Canister Methods ===== //
+// Replicas must agree on the response, so drop the headers and reduce the body to the price.
+// A body that is not a valid price is emptied and rejected by the update call.
+#[query]
+fn transform_price_response(args: TransformArgs) -> HttpRequestResult {
+    let TransformArgs { response, context } = args;
+    let body = if response.status == 200u16 {
+        std::str::from_utf8(&context)
+            .ok()
+            .and_then(|path| parse_price(&response.body, path))
+            .map(|price| price.to_string().into_bytes())
+            .unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    HttpRequestResult {
+        status: response.status,
+        headers: Vec::new(),
+        body,
+    }
+}
+
+#[query]
+fn get_price(symbol: String) -> Option<PriceInfo> {
+    let config = config();
+    config.feed(&symbol)?;
+    Some(price_info(&symbol, config.max_price_age))
+}
+
+/// Prices of every configured feed
+#[query]
+fn get_price_info() -> Vec<(String, PriceInfo)> {
+    let config = config();
+    config
+        .feeds
+        .iter()
+        .map(|feed| {
+            (
+                feed.symbol.clone(),
+                price_info(&feed.symbol, config.max_price_age),
+            )
+        })
+        .collect()
+}
+
+#[query]
+fn get_cross_rate(base: String, quote: String) -> Result<Price, LendingError> {
+    cross_rate(&base, &quote)
+}
+
+#[query]
+fn get_oracle_config() -> OracleConfig {
+    config()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quotes(prices: &[(&str, u64)]) -> Vec<(String, Price)> {
+        prices
+            .iter()
+            .map(|(name, dollars)| (name.to_string(), Price::dollars(*dollars)))
+            .collect()
+    }
+
+    #[test]
+    fn median_of_odd_and_even_counts() {
+        assert_eq!(median(&[7]), 7);
+        assert_eq!(median(&[1, 5, 9]), 5);
+        assert_eq!(median(&[1, 4, 6, 9]), 5);
+        assert_eq!(median(&[u64::MAX - 1, u64::MAX]), u64::MAX - 1);
+    }
+
+    #[test]
+    fn aggregates_agreeing_sources() {
+        let (price, sources) = aggregate(
+            quotes(&[
+                ("kraken", 64_100),
+                ("coingecko", 64_000),
+                ("coinbase", 64_050),
+            ]),
+            200,
+            2,
+        )
+        .unwrap();
+        assert_eq!(price, Price::new(6_405_000_000_000));
+        assert_eq!(sources, vec!["coingecko", "coinbase", "kraken"]);
+    }
+
+    #[test]
+    fn drops_outliers_before_taking_the_median() {
+        let (price, sources) = aggregate(
+            quotes(&[
+                ("coingecko", 64_000),
+                ("coinbase", 64_200),
+                ("kraken", 90_000),
+            ]),
+            200,
+            2,
+        )
+        .unwrap();
+        assert_eq!(price, Price::new(6_410_000_000_000));
+        assert_eq!(sources, vec!["coingecko", "coinbase"]);
+    }
+
+    #[test]
+    fn accepts_quotes_exactly_at_the_max_deviation() {
+        // 2% of the 100 median
+        let (_, sources) = aggregate(quotes(&[("a", 98), ("b", 100), ("c", 102)]), 200, 3).unwrap();
+        assert_eq!(sources.len(), 3);
+        assert!(aggregate(quotes(&[("a", 97), ("b", 100), ("c", 102)]), 200, 3).is_err());
+    }
+
+    #[test]
+    fn fails_when_too_few_sources_agree() {
+        let result = aggregate(
+            quotes(&[
+                ("coingecko", 64_000),
+                ("coinbase", 80_000),
+                ("kraken", 96_000),
+            ]),
+            200,
+            2,
+        );
+        assert!(matches!(
+            result,
+            Err(LendingError::PriceSourcesDisagree {
+                agreeing: 1,
+                required: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn fails_without_quotes() {
+        assert!(matches!(
+            aggregate(Vec::new(), 200, 1),
+            Err(LendingError::PriceSourcesDisagree {
+                agreeing: 0,
+                required: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn single_source_feeds_need_only_one_quote() {
+        let (price, sources) = aggregate(quotes(&[("xrc", 64_000)]), 200, 1).unwrap();
+        assert_eq!(price, Price::new(6_400_000_000_000));
+        assert_eq!(sources, vec!["xrc"]);
+    }
 }
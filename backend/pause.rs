@@ -0,0 +1,102 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::StableCell;
+use std::cell::RefCell;
+
+use crate::error::LendingError;
+use crate::roles::{require_role, Role};
+use crate::{memory, Memory, PAUSE_STATE_MEMORY_ID};
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Deposit,
+    Borrow,
+    Withdraw,
+    Repay,
+    Liquidate,
+    FlashLoan,
+    Supply,
+    Redeem,
+}
+
+#[derive(CandidType, Deserialize, Default, Clone)]
+pub struct PauseState {
+    pub paused: Vec<Operation>,
+    /// Only repay, withdraw and redeem are allowed while winding down
+    pub wind_down: bool,
+}
+
+impl PauseState {
+    fn is_paused(&self, operation: Operation) -> bool {
+        let wound_down = self.wind_down
+            && !matches!(
+                operation,
+                Operation::Repay | Operation::Withdraw | Operation::Redeem
+            );
+        wound_down || self.paused.contains(&operation)
+    }
+}
+
+impl_candid_storable!(PauseState);
+
+thread_local! {
+    static PAUSE_STATE: RefCell<StableCell<PauseState, Memory>> = RefCell::new(
+        StableCell::init(memory(PAUSE_STATE_MEMORY_ID), PauseState::default())
+            .expect("Failed to init pause state")
+    );
+}
+
+fn update_state(f: impl FnOnce(&mut PauseState)) {
+    PAUSE_STATE.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let mut state = cell.get().clone();
+        f(&mut state);
+        cell.set(state).expect("Failed to save pause state");
+    });
+}
+
+/// Fails when `operation` is paused or not allowed during wind-down
+pub fn ensure_active(operation: Operation) -> Result<(), LendingError> {
+    if PAUSE_STATE.with(|cell| cell.borrow().get().is_paused(operation)) {
+        return Err(LendingError::Paused { operation });
+    }
+    Ok(())
+}
+
+// ===== Canister Methods ===== //
+#[update]
+fn pause(operation: Operation) -> Result<(), LendingError> {
+    require_role(Role::Pauser)?;
+    update_state(|s| {
+        if !s.paused.contains(&operation) {
+            s.paused.push(operation);
+        }
+    });
+    ic_cdk::println!("{:?} paused by {}", operation, ic_cdk::api::msg_caller());
+    Ok(())
+}
+
+#[update]
+fn unpause(operation: Operation) -> Result<(), LendingError> {
+    require_role(Role::Pauser)?;
+    update_state(|s| s.paused.retain(|op| *op != operation));
+    ic_cdk::println!("{:?} unpaused by {}", operation, ic_cdk::api::msg_caller());
+    Ok(())
+}
+
+#[update]
+fn set_wind_down(enabled: bool) -> Result<(), LendingError> {
+    require_role(Role::Pauser)?;
+    update_state(|s| s.wind_down = enabled);
+    ic_cdk::println!(
+        "Wind-down set to {} by {}",
+        enabled,
+        ic_cdk::api::msg_caller()
+    );
+    Ok(())
+}
+
+#[query]
+fn get_pause_state() -> PauseState {
+    PAUSE_STATE.with(|cell| cell.borrow().get().clone())
+}
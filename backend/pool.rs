@@ -0,0 +1,189 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::error::LendingError;
+use crate::pause::{self, Operation};
+use crate::{flash_loan, icrc1_transfer, icrc2_transfer_from, interest, Account};
+use crate::{memory, Asset, Memory, POOL_SHARES_MEMORY_ID, SUPPLIER_SHARES_MEMORY_ID};
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct SupplierKey {
+    owner: Principal,
+    asset: Asset,
+}
+
+/// Lender position in one pool
+#[derive(CandidType, Deserialize, Default, Clone)]
+pub struct SupplyBalance {
+    pub shares: u64,
+    /// Value of the shares at the current exchange rate
+    pub amount: u64,
+}
+
+/// Liquidity overview of one pool
+#[derive(CandidType, Deserialize, Clone)]
+pub struct PoolInfo {
+    pub total_supply: u64,
+    pub total_borrows: u64,
+    pub total_shares: u64,
+    pub available_liquidity: u64,
+}
+
+impl_candid_storable!(SupplierKey);
+
+thread_local! {
+    static TOTAL_SHARES: RefCell<StableBTreeMap<Asset, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(POOL_SHARES_MEMORY_ID))
+    );
+
+    static SUPPLIER_SHARES: RefCell<StableBTreeMap<SupplierKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(SUPPLIER_SHARES_MEMORY_ID))
+    );
+}
+
+fn total_shares(asset: Asset) -> u64 {
+    TOTAL_SHARES.with(|t| t.borrow().get(&asset).unwrap_or(0))
+}
+
+fn shares_of(owner: Principal, asset: Asset) -> u64 {
+    SUPPLIER_SHARES.with(|s| s.borrow().get(&SupplierKey { owner, asset }).unwrap_or(0))
+}
+
+fn set_shares(owner: Principal, asset: Asset, shares: u64, total: u64) {
+    SUPPLIER_SHARES.with(|s| {
+        let mut map = s.borrow_mut();
+        let key = SupplierKey { owner, asset };
+        if shares == 0 {
+            map.remove(&key);
+        } else {
+            map.insert(key, shares);
+        }
+    });
+    TOTAL_SHARES.with(|t| t.borrow_mut().insert(asset, total));
+}
+
+// Underlying amount worth `shares`, rounded down
+fn shares_to_amount(shares: u64, total_shares: u64, total_supply: u64) -> u64 {
+    if total_shares == 0 {
+        return 0;
+    }
+    (shares as u128 * total_supply as u128 / total_shares as u128) as u64
+}
+
+fn pool_info(market: &interest::BorrowIndex, asset: Asset) -> PoolInfo {
+    let total_borrows = interest::to_debt(market.total_scaled_debt, market.index);
+    PoolInfo {
+        total_supply: market.total_supply,
+        total_borrows,
+        total_shares: total_shares(asset),
+        available_liquidity: market
+            .total_supply
+            .saturating_sub(total_borrows)
+            .saturating_sub(flash_loan::in_flight(asset))
+            .saturating_sub(flash_loan::unpaid(asset)),
+    }
+}
+
+/// Liquidity that can still be borrowed or redeemed
+pub fn available_liquidity(asset: Asset) -> u64 {
+    pool_info(&interest::current(asset), asset).available_liquidity
+}
+
+// Shares `amount` is worth at the current exchange rate, the first lender sets it to 1:1
+fn shares_for(amount: u64, total_shares: u64, total_supply: u64) -> u64 {
+    if total_shares == 0 || total_supply == 0 {
+        amount
+    } else {
+        (amount as u128 * total_shares as u128 / total_supply as u128) as u64
+    }
+}
+
+// ===== Canister Methods ===== //
+// The lender must approve `amount` plus the ledger fee to this canister first
+#[update]
+async fn supply(asset: Asset, amount: u64) -> Result<u64, LendingError> {
+    pause::ensure_active(Operation::Supply)?;
+    if amount == 0 {
+        return Err(LendingError::InvalidAmount);
+    }
+    let user = ic_cdk::api::msg_caller();
+    let market = interest::current(asset);
+    if shares_for(amount, total_shares(asset), market.total_supply) == 0 {
+        return Err(LendingError::InvalidAmount);
+    }
+
+    // Shares are only minted for liquidity that has arrived
+    let from = Account {
+        owner: user,
+        subaccount: None,
+    };
+    icrc2_transfer_from(asset, from, Nat::from(amount)).await?;
+
+    interest::accrue(asset);
+    let market = interest::current(asset);
+    let total = total_shares(asset);
+    let minted = shares_for(amount, total, market.total_supply);
+    set_shares(user, asset, shares_of(user, asset) + minted, total + minted);
+    interest::update_total_supply(asset, amount, 0);
+    Ok(minted)
+}
+
+// The lender receives the redeemed amount minus the ledger fee
+#[update]
+async fn redeem(asset: Asset, shares: u64) -> Result<u64, LendingError> {
+    pause::ensure_active(Operation::Redeem)?;
+    if shares == 0 {
+        return Err(LendingError::InvalidAmount);
+    }
+    let user = ic_cdk::api::msg_caller();
+    interest::accrue(asset);
+    let market = interest::current(asset);
+    let owned = shares_of(user, asset);
+    if shares > owned {
+        return Err(LendingError::InsufficientShares { owned });
+    }
+
+    let total = total_shares(asset);
+    let amount = shares_to_amount(shares, total, market.total_supply);
+    let available = pool_info(&market, asset).available_liquidity;
+    if amount > available {
+        return Err(LendingError::InsufficientLiquidity { available });
+    }
+
+    // Burn before the transfer so concurrent calls cannot redeem twice
+    set_shares(user, asset, owned - shares, total - shares);
+    interest::update_total_supply(asset, 0, amount);
+    let to = Account {
+        owner: user,
+        subaccount: None,
+    };
+    if let Err(e) = icrc1_transfer(asset, to, Nat::from(amount)).await {
+        interest::accrue(asset);
+        set_shares(
+            user,
+            asset,
+            shares_of(user, asset) + shares,
+            total_shares(asset) + shares,
+        );
+        interest::update_total_supply(asset, amount, 0);
+        return Err(e);
+    }
+    Ok(amount)
+}
+
+#[query]
+fn get_supply_balance(owner: Principal, asset: Asset) -> SupplyBalance {
+    let market = interest::current(asset);
+    let shares = shares_of(owner, asset);
+    SupplyBalance {
+        shares,
+        amount: shares_to_amount(shares, total_shares(asset), market.total_supply),
+    }
+}
+
+#[query]
+fn get_pool_info(asset: Asset) -> PoolInfo {
+    pool_info(&interest::current(asset), asset)
+}
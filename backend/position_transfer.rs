@@ -0,0 +1,116 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::delegation;
+use crate::error::LendingError;
+use crate::{memory, Memory, PositionKey, LOANS, POSITION_TRANSFERS_MEMORY_ID};
+
+/// Position offered to a new owner, it only moves once they accept
+#[derive(CandidType, Deserialize, Clone)]
+pub struct PositionTransfer {
+    pub position_id: u64,
+    pub from: Principal,
+    pub to: Principal,
+    pub created_at: u64,
+}
+
+impl_candid_storable!(PositionTransfer);
+
+thread_local! {
+    static TRANSFERS: RefCell<StableBTreeMap<u64, PositionTransfer, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(POSITION_TRANSFERS_MEMORY_ID))
+    );
+}
+
+fn pending(position_id: u64) -> Option<PositionTransfer> {
+    TRANSFERS.with(|t| t.borrow().get(&position_id))
+}
+
+/// Drop the pending offer of a position that no longer exists
+pub fn clear(position_id: u64) {
+    TRANSFERS.with(|t| t.borrow_mut().remove(&position_id));
+}
+
+// ===== Canister Methods ===== //
+// Offering the same position again replaces the previous offer
+#[update]
+fn transfer_position(position_id: u64, new_owner: Principal) -> Result<(), LendingError> {
+    let owner = ic_cdk::api::msg_caller();
+    if new_owner == owner || new_owner == Principal::anonymous() {
+        return Err(LendingError::InvalidRecipient);
+    }
+    let key = PositionKey { owner, position_id };
+    if !LOANS.with(|loans| loans.borrow().contains_key(&key)) {
+        return Err(LendingError::NoPosition);
+    }
+    let transfer = PositionTransfer {
+        position_id,
+        from: owner,
+        to: new_owner,
+        created_at: ic_cdk::api::time(),
+    };
+    TRANSFERS.with(|t| t.borrow_mut().insert(position_id, transfer));
+    Ok(())
+}
+
+#[update]
+fn cancel_position_transfer(position_id: u64) -> Result<(), LendingError> {
+    let caller = ic_cdk::api::msg_caller();
+    match pending(position_id) {
+        // Either side can call the transfer off
+        Some(transfer) if transfer.from == caller || transfer.to == caller => {
+            clear(position_id);
+            Ok(())
+        }
+        _ => Err(LendingError::PositionTransferNotFound),
+    }
+}
+
+#[update]
+fn accept_position(position_id: u64) -> Result<(), LendingError> {
+    let caller = ic_cdk::api::msg_caller();
+    let transfer = pending(position_id)
+        .filter(|transfer| transfer.to == caller)
+        .ok_or(LendingError::PositionTransferNotFound)?;
+
+    let from = PositionKey {
+        owner: transfer.from,
+        position_id,
+    };
+    let to = PositionKey {
+        owner: caller,
+        position_id,
+    };
+    LOANS.with(|loans| {
+        let mut map = loans.borrow_mut();
+        let loan = map.remove(&from).ok_or(LendingError::NoPosition)?;
+        map.insert(to, loan);
+        Ok::<_, LendingError>(())
+    })?;
+    clear(position_id);
+    // Delegates were chosen by the previous owner
+    delegation::clear(position_id);
+
+    ic_cdk::println!(
+        "Position {} moved from {} to {}",
+        position_id,
+        transfer.from.to_text(),
+        caller.to_text()
+    );
+    Ok(())
+}
+
+/// Transfers the caller is sending or receiving
+#[query]
+fn list_position_transfers() -> Vec<PositionTransfer> {
+    let caller = ic_cdk::api::msg_caller();
+    TRANSFERS.with(|t| {
+        t.borrow()
+            .iter()
+            .map(|(_, transfer)| transfer)
+            .filter(|transfer| transfer.from == caller || transfer.to == caller)
+            .collect()
+    })
+}
@@ -0,0 +1,183 @@
+use candid::{CandidType, Deserialize};
+use std::fmt;
+
+/// Fixed-point USD price of one whole token, worth `mantissa / 10^decimals`
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Price {
+    pub mantissa: u64,
+    pub decimals: u32,
+}
+
+impl_candid_storable!(Price);
+
+impl Default for Price {
+    fn default() -> Self {
+        Price::new(0)
+    }
+}
+
+impl Price {
+    /// Every price the oracle keeps is normalized to this many decimals
+    pub const DECIMALS: u32 = 8;
+
+    /// Price with `mantissa` given in `Price::DECIMALS`
+    pub fn new(mantissa: u64) -> Self {
+        Price {
+            mantissa,
+            decimals: Self::DECIMALS,
+        }
+    }
+
+    /// Price of a whole number of dollars
+    #[cfg(test)]
+    pub fn dollars(dollars: u64) -> Self {
+        Price::new(dollars * 10u64.pow(Self::DECIMALS))
+    }
+
+    /// Price of `mantissa / 10^decimals`, digits beyond `Price::DECIMALS` are truncated
+    pub fn from_parts(mantissa: u128, decimals: u32) -> Option<Self> {
+        let mantissa = if decimals >= Self::DECIMALS {
+            mantissa / 10u128.checked_pow(decimals - Self::DECIMALS)?
+        } else {
+            mantissa.checked_mul(10u128.checked_pow(Self::DECIMALS - decimals)?)?
+        };
+        u64::try_from(mantissa).ok().map(Price::new)
+    }
+
+    /// Parse a non-negative decimal such as `64012.5` or `6.4e4` without going through
+    /// floating point. Digits beyond `Price::DECIMALS` are truncated.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (number, exponent) = match text.find(['e', 'E']) {
+            Some(i) => (&text[..i], text[i + 1..].parse::<i32>().ok()?),
+            None => (text, 0),
+        };
+        let (int, frac) = number.split_once('.').unwrap_or((number, ""));
+        if (int.is_empty() && frac.is_empty())
+            || !int.chars().chain(frac.chars()).all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+
+        // `number` is `digits / 10^frac.len()`, move the point to `Price::DECIMALS`
+        let digits: String = int.chars().chain(frac.chars()).collect();
+        let shift = Self::DECIMALS as i64 + exponent as i64 - frac.len() as i64;
+        let kept = if shift >= 0 {
+            digits.as_str()
+        } else {
+            &digits[..digits.len().saturating_sub(shift.unsigned_abs() as usize)]
+        };
+        let kept = kept.trim_start_matches('0');
+        let base = if kept.is_empty() {
+            0
+        } else {
+            kept.parse::<u128>().ok()?
+        };
+        let mantissa = base.checked_mul(10u128.checked_pow(shift.max(0) as u32)?)?;
+        u64::try_from(mantissa).ok().map(Price::new)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mantissa == 0
+    }
+}
+
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scale = 10u128.pow(self.decimals);
+        let mantissa = self.mantissa as u128;
+        if self.decimals == 0 {
+            return write!(f, "{}", mantissa);
+        }
+        write!(
+            f,
+            "{}.{:0width$}",
+            mantissa / scale,
+            mantissa % scale,
+            width = self.decimals as usize
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Option<u64> {
+        Price::parse(text).map(|price| price.mantissa)
+    }
+
+    #[test]
+    fn parses_integers_and_decimals() {
+        assert_eq!(parse("64012"), Some(6_401_200_000_000));
+        assert_eq!(parse("64012.5"), Some(6_401_250_000_000));
+        assert_eq!(parse("0.00000001"), Some(1));
+        assert_eq!(parse(".5"), Some(50_000_000));
+        assert_eq!(parse("5."), Some(500_000_000));
+        assert_eq!(parse("0"), Some(0));
+    }
+
+    #[test]
+    fn ignores_leading_zeros() {
+        assert_eq!(parse("007.5"), Some(750_000_000));
+        assert_eq!(parse("0000"), Some(0));
+    }
+
+    #[test]
+    fn truncates_digits_beyond_decimals() {
+        assert_eq!(parse("1.123456789"), Some(112_345_678));
+        assert_eq!(parse("0.000000009"), Some(0));
+    }
+
+    #[test]
+    fn applies_exponents() {
+        assert_eq!(parse("6.4e4"), Some(6_400_000_000_000));
+        assert_eq!(parse("6.4E4"), Some(6_400_000_000_000));
+        assert_eq!(parse("1e-8"), Some(1));
+        assert_eq!(parse("1e-9"), Some(0));
+        assert_eq!(parse("1e-100"), Some(0));
+        assert_eq!(parse("12345e-2"), Some(12_345_000_000));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for text in [
+            "", ".", "1e", "e5", "1.2.3", "abc", "1,5", " 1", "1e+", "0x10",
+        ] {
+            assert_eq!(parse(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_signs() {
+        assert_eq!(parse("-1"), None);
+        assert_eq!(parse("+1"), None);
+    }
+
+    #[test]
+    fn rejects_overflow() {
+        // u64::MAX mantissa is about 1.8e11 whole units
+        assert_eq!(parse("184467440737.09551615"), Some(u64::MAX));
+        assert_eq!(parse("184467440737.09551616"), None);
+        assert_eq!(parse("1e100"), None);
+        assert_eq!(parse("99999999999999999999999999999999999999999"), None);
+    }
+
+    #[test]
+    fn normalizes_parts() {
+        assert_eq!(
+            Price::from_parts(6_401_250, 2),
+            Some(Price::new(6_401_250_000_000))
+        );
+        assert_eq!(
+            Price::from_parts(123_456_789_012, 10),
+            Some(Price::new(1_234_567_890))
+        );
+        assert_eq!(Price::from_parts(u128::MAX, 0), None);
+    }
+
+    #[test]
+    fn displays_all_decimals() {
+        assert_eq!(Price::new(6_401_250_000_000).to_string(), "64012.50000000");
+        assert_eq!(Price::new(1).to_string(), "0.00000001");
+    }
+}
@@ -0,0 +1,368 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::error::LendingError;
+use crate::oracle::{self, within, OracleConfig, TokenPrice};
+use crate::price::Price;
+use crate::roles::{require_role, Role};
+use crate::{memory, Memory, BREAKER_EVENTS_MEMORY_ID, QUARANTINES_MEMORY_ID};
+use crate::{COLLATERAL_ASSET, DEBT_ASSET};
+
+/// Price held back because it moved too far from the last accepted one
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Quarantine {
+    pub price: Price,
+    pub sources: Vec<String>,
+    pub since: u64,
+    /// Later readings that agreed with the quarantined price
+    pub confirmations: u32,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub enum BreakerEventKind {
+    Tripped,
+    /// Enough later readings agreed with the quarantined price
+    ConfirmedByReadings,
+    /// A later reading came back close to the last accepted price
+    Recovered,
+    /// A later reading agreed with neither price and replaced the quarantined one
+    Restarted,
+    ConfirmedByAdmin {
+        admin: Principal,
+    },
+    RejectedByAdmin {
+        admin: Principal,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct BreakerEvent {
+    pub id: u64,
+    pub feed: String,
+    pub timestamp: u64,
+    pub kind: BreakerEventKind,
+    pub previous_price: Price,
+    pub price: Price,
+}
+
+impl_candid_storable!(Quarantine, BreakerEvent);
+
+thread_local! {
+    // Borrowing is paused while the collateral or debt price is quarantined
+    static QUARANTINES: RefCell<StableBTreeMap<String, Quarantine, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(QUARANTINES_MEMORY_ID))
+    );
+
+    static EVENTS: RefCell<StableBTreeMap<u64, BreakerEvent, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(BREAKER_EVENTS_MEMORY_ID))
+    );
+}
+
+fn quarantine(feed: &str) -> Option<Quarantine> {
+    QUARANTINES.with(|q| q.borrow().get(&feed.to_string()))
+}
+
+fn set_quarantine(feed: &str, quarantine: Option<Quarantine>) {
+    QUARANTINES.with(|q| {
+        let mut quarantines = q.borrow_mut();
+        match quarantine {
+            Some(quarantine) => quarantines.insert(feed.to_string(), quarantine),
+            None => quarantines.remove(&feed.to_string()),
+        }
+    });
+}
+
+fn quarantined(feed: &str, price: Price) -> LendingError {
+    LendingError::PriceQuarantined {
+        feed: feed.to_string(),
+        price,
+    }
+}
+
+fn log(feed: &str, kind: BreakerEventKind, previous_price: Price, price: Price) {
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let id = events.len();
+        events.insert(
+            id,
+            BreakerEvent {
+                id,
+                feed: feed.to_string(),
+                timestamp: ic_cdk::api::time(),
+                kind,
+                previous_price,
+                price,
+            },
+        );
+    });
+}
+
+/// Fails while a quarantined collateral or debt price keeps borrowing paused
+pub fn ensure_clear() -> Result<(), LendingError> {
+    for feed in [COLLATERAL_ASSET.symbol(), DEBT_ASSET.symbol()] {
+        if let Some(held) = quarantine(feed) {
+            return Err(quarantined(feed, held.price));
+        }
+    }
+    Ok(())
+}
+
+// Breaker state left by a reading, along with the event to log as (kind, previous, price)
+struct Outcome {
+    quarantine: Option<Quarantine>,
+    event: Option<(BreakerEventKind, Price, Price)>,
+}
+
+fn hold(price: Price, sources: &[String], now: u64) -> Option<Quarantine> {
+    Some(Quarantine {
+        price,
+        sources: sources.to_vec(),
+        since: now,
+        confirmations: 0,
+    })
+}
+
+// The reading is accepted when no quarantine is left
+fn evaluate(
+    held: Option<Quarantine>,
+    price: Price,
+    sources: &[String],
+    last: &TokenPrice,
+    now: u64,
+    config: &OracleConfig,
+) -> Outcome {
+    if let Some(mut held) = held {
+        if within(price, held.price, config.max_price_jump_bps) {
+            held.confirmations += 1;
+            if held.confirmations >= config.breaker_confirmations {
+                return Outcome {
+                    quarantine: None,
+                    event: Some((BreakerEventKind::ConfirmedByReadings, last.price, price)),
+                };
+            }
+            return Outcome {
+                quarantine: Some(held),
+                event: None,
+            };
+        }
+        if within(price, last.price, config.max_price_jump_bps) {
+            return Outcome {
+                quarantine: None,
+                event: Some((BreakerEventKind::Recovered, held.price, price)),
+            };
+        }
+        // Neither side agrees, start over with the newest reading
+        return Outcome {
+            quarantine: hold(price, sources, now),
+            event: Some((BreakerEventKind::Restarted, held.price, price)),
+        };
+    }
+
+    let recent =
+        !last.price.is_zero() && now.saturating_sub(last.last_updated) <= config.price_jump_window;
+    if recent && !within(price, last.price, config.max_price_jump_bps) {
+        return Outcome {
+            quarantine: hold(price, sources, now),
+            event: Some((BreakerEventKind::Tripped, last.price, price)),
+        };
+    }
+    Outcome {
+        quarantine: None,
+        event: None,
+    }
+}
+
+/// Decide whether a freshly aggregated price of `feed` can replace `last`.
+/// Returns `PriceQuarantined` when the price has to wait for confirmation.
+pub fn check(
+    feed: &str,
+    price: Price,
+    sources: &[String],
+    last: &TokenPrice,
+) -> Result<(), LendingError> {
+    let config = oracle::config();
+    let outcome = evaluate(
+        quarantine(feed),
+        price,
+        sources,
+        last,
+        ic_cdk::api::time(),
+        &config,
+    );
+    set_quarantine(feed, outcome.quarantine.clone());
+    if let Some((kind, previous, price)) = outcome.event {
+        if matches!(kind, BreakerEventKind::Tripped) {
+            ic_cdk::println!(
+                "Price breaker tripped for {}: {} -> {}",
+                feed,
+                previous,
+                price
+            );
+        }
+        log(feed, kind, previous, price);
+    }
+    match outcome.quarantine {
+        Some(held) => Err(quarantined(feed, held.price)),
+        None => Ok(()),
+    }
+}
+
+// ===== Canister Methods ===== //
+#[update]
+fn confirm_quarantined_price(feed: String) -> Result<(), LendingError> {
+    require_role(Role::Admin)?;
+    let held = quarantine(&feed).ok_or(LendingError::NoQuarantinedPrice)?;
+    let previous = oracle::token_price(&feed).price;
+    oracle::store_price(&feed, held.price, held.sources, ic_cdk::api::time());
+    set_quarantine(&feed, None);
+    log(
+        &feed,
+        BreakerEventKind::ConfirmedByAdmin {
+            admin: ic_cdk::api::msg_caller(),
+        },
+        previous,
+        held.price,
+    );
+    Ok(())
+}
+
+#[update]
+fn reject_quarantined_price(feed: String) -> Result<(), LendingError> {
+    require_role(Role::Admin)?;
+    let held = quarantine(&feed).ok_or(LendingError::NoQuarantinedPrice)?;
+    set_quarantine(&feed, None);
+    log(
+        &feed,
+        BreakerEventKind::RejectedByAdmin {
+            admin: ic_cdk::api::msg_caller(),
+        },
+        oracle::token_price(&feed).price,
+        held.price,
+    );
+    Ok(())
+}
+
+/// Prices currently held back, keyed by feed
+#[query]
+fn get_quarantined_prices() -> Vec<(String, Quarantine)> {
+    QUARANTINES.with(|q| q.borrow().iter().collect())
+}
+
+#[query]
+fn get_price_breaker_events() -> Vec<BreakerEvent> {
+    EVENTS.with(|events| events.borrow().iter().map(|(_, event)| event).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: u64 = 60 * 1_000_000_000;
+
+    fn last(dollars: u64, last_updated: u64) -> TokenPrice {
+        TokenPrice {
+            price: Price::dollars(dollars),
+            last_updated,
+            sources: Vec::new(),
+        }
+    }
+
+    fn held(dollars: u64, confirmations: u32) -> Option<Quarantine> {
+        Some(Quarantine {
+            price: Price::dollars(dollars),
+            sources: Vec::new(),
+            since: 0,
+            confirmations,
+        })
+    }
+
+    fn evaluate_at(held: Option<Quarantine>, dollars: u64, last: &TokenPrice) -> Outcome {
+        evaluate(
+            held,
+            Price::dollars(dollars),
+            &[],
+            last,
+            10 * MINUTE,
+            &OracleConfig::default(),
+        )
+    }
+
+    #[test]
+    fn accepts_moves_within_the_max_jump() {
+        let outcome = evaluate_at(None, 110, &last(100, 5 * MINUTE));
+        assert!(outcome.quarantine.is_none());
+        assert!(outcome.event.is_none());
+    }
+
+    #[test]
+    fn trips_on_a_large_jump_from_a_recent_price() {
+        let outcome = evaluate_at(None, 111, &last(100, 5 * MINUTE));
+        assert_eq!(
+            outcome.quarantine.map(|q| q.price),
+            Some(Price::dollars(111))
+        );
+        assert!(matches!(
+            outcome.event,
+            Some((BreakerEventKind::Tripped, previous, new)) if previous == Price::dollars(100) && new == Price::dollars(111)
+        ));
+    }
+
+    #[test]
+    fn ignores_jumps_from_old_or_missing_prices() {
+        let stale = last(100, 0);
+        let outcome = evaluate(
+            None,
+            Price::dollars(200),
+            &[],
+            &stale,
+            61 * MINUTE,
+            &OracleConfig::default(),
+        );
+        assert!(outcome.quarantine.is_none());
+        assert!(evaluate_at(None, 200, &TokenPrice::default())
+            .quarantine
+            .is_none());
+    }
+
+    #[test]
+    fn counts_agreeing_readings_until_confirmed() {
+        let last = last(100, 5 * MINUTE);
+        let outcome = evaluate_at(held(150, 1), 151, &last);
+        let quarantine = outcome.quarantine.unwrap();
+        assert_eq!(quarantine.price, Price::dollars(150));
+        assert_eq!(quarantine.confirmations, 2);
+        assert!(outcome.event.is_none());
+
+        let outcome = evaluate_at(Some(quarantine), 149, &last);
+        assert!(outcome.quarantine.is_none());
+        assert!(matches!(
+            outcome.event,
+            Some((BreakerEventKind::ConfirmedByReadings, _, new)) if new == Price::dollars(149)
+        ));
+    }
+
+    #[test]
+    fn recovers_when_readings_return_to_the_last_price() {
+        let outcome = evaluate_at(held(150, 2), 101, &last(100, 5 * MINUTE));
+        assert!(outcome.quarantine.is_none());
+        assert!(matches!(
+            outcome.event,
+            Some((BreakerEventKind::Recovered, previous, _)) if previous == Price::dollars(150)
+        ));
+    }
+
+    #[test]
+    fn restarts_the_quarantine_when_nothing_agrees() {
+        let outcome = evaluate_at(held(150, 2), 200, &last(100, 5 * MINUTE));
+        let quarantine = outcome.quarantine.unwrap();
+        assert_eq!(quarantine.price, Price::dollars(200));
+        assert_eq!(quarantine.confirmations, 0);
+        assert_eq!(quarantine.since, 10 * MINUTE);
+        assert!(matches!(
+            outcome.event,
+            Some((BreakerEventKind::Restarted, previous, new)) if previous == Price::dollars(150) && new == Price::dollars(200)
+        ));
+    }
+}
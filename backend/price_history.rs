@@ -0,0 +1,202 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::query;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::price::Price;
+use crate::{memory, Memory, PRICE_HISTORY_LEN_MEMORY_ID, PRICE_HISTORY_MEMORY_ID};
+
+// Points kept per feed, about 3.5 days at the default refresh interval
+const HISTORY_CAPACITY: u64 = 1_000;
+// Points returned by one history query
+const MAX_QUERY_POINTS: usize = 500;
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct PointKey {
+    feed: String,
+    timestamp: u64,
+}
+
+/// One accepted price of a feed
+#[derive(CandidType, Deserialize, Clone)]
+pub struct PricePoint {
+    pub timestamp: u64,
+    pub price: Price,
+}
+
+impl_candid_storable!(PointKey);
+
+thread_local! {
+    static HISTORY: RefCell<StableBTreeMap<PointKey, Price, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(PRICE_HISTORY_MEMORY_ID))
+    );
+
+    static LENGTHS: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(PRICE_HISTORY_LEN_MEMORY_ID))
+    );
+}
+
+fn first_key(feed: &str, timestamp: u64) -> PointKey {
+    PointKey {
+        feed: feed.to_string(),
+        timestamp,
+    }
+}
+
+// Points of `feed` from `from` up to and including `to`, oldest first
+fn points(feed: &str, from: u64, to: u64) -> Vec<PricePoint> {
+    HISTORY.with(|h| {
+        h.borrow()
+            .range(first_key(feed, from)..)
+            .take_while(|(key, _)| key.feed == feed && key.timestamp <= to)
+            .map(|(key, price)| PricePoint {
+                timestamp: key.timestamp,
+                price,
+            })
+            .collect()
+    })
+}
+
+// Latest point of `feed` at or before `timestamp`
+fn point_before(feed: &str, timestamp: u64) -> Option<PricePoint> {
+    HISTORY.with(|h| {
+        h.borrow()
+            .range(first_key(feed, 0)..=first_key(feed, timestamp))
+            .next_back()
+            .map(|(key, price)| PricePoint {
+                timestamp: key.timestamp,
+                price,
+            })
+    })
+}
+
+/// Append an accepted price, dropping the oldest point once the feed is full
+pub fn record(feed: &str, price: Price, timestamp: u64) {
+    let len = LENGTHS.with(|l| l.borrow().get(&feed.to_string()).unwrap_or(0));
+    HISTORY.with(|h| {
+        let mut history = h.borrow_mut();
+        let inserted = history.insert(first_key(feed, timestamp), price).is_none();
+        if !inserted {
+            return;
+        }
+        let oldest = history
+            .range(first_key(feed, 0)..)
+            .next()
+            .map(|(key, _)| key)
+            .filter(|_| len >= HISTORY_CAPACITY);
+        let len = match oldest {
+            Some(key) => {
+                history.remove(&key);
+                len
+            }
+            None => len + 1,
+        };
+        LENGTHS.with(|l| l.borrow_mut().insert(feed.to_string(), len));
+    });
+}
+
+/// Time-weighted average price of `feed` over the `window` nanoseconds before `now`.
+/// Each price counts for as long as it was the latest one.
+pub fn twap(feed: &str, window: u64, now: u64) -> Option<Price> {
+    let start = now.saturating_sub(window);
+    let mut series: Vec<PricePoint> = point_before(feed, start)
+        .map(|point| PricePoint {
+            timestamp: start,
+            price: point.price,
+        })
+        .into_iter()
+        .collect();
+    series.extend(points(feed, start.saturating_add(1), now));
+    let first = series.first()?.timestamp;
+    if first >= now {
+        return series.last().map(|point| point.price);
+    }
+
+    let weighted: u128 = series
+        .iter()
+        .zip(
+            series
+                .iter()
+                .skip(1)
+                .map(|point| point.timestamp)
+                .chain([now]),
+        )
+        .map(|(point, until)| point.price.mantissa as u128 * (until - point.timestamp) as u128)
+        .sum();
+    Some(Price::new((weighted / (now - first) as u128) as u64))
+}
+
+// ===== Canister Methods ===== //
+// At most MAX_QUERY_POINTS points are returned, page by moving `from` past the last one
+#[query]
+fn get_price_history(feed: String, from: u64, to: u64) -> Vec<PricePoint> {
+    let mut history = points(&feed, from, to);
+    history.truncate(MAX_QUERY_POINTS);
+    history
+}
+
+#[query]
+fn get_twap(feed: String, window: u64) -> Option<Price> {
+    twap(&feed, window, ic_cdk::api::time())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_history_has_no_twap() {
+        assert_eq!(twap("empty", 100, 1_000), None);
+    }
+
+    #[test]
+    fn price_before_the_window_counts_for_the_whole_window() {
+        record("flat", Price::dollars(100), 0);
+        assert_eq!(twap("flat", 50, 100), Some(Price::dollars(100)));
+    }
+
+    #[test]
+    fn weights_prices_by_how_long_they_were_latest() {
+        record("weighted", Price::dollars(100), 0);
+        record("weighted", Price::dollars(200), 75);
+        assert_eq!(twap("weighted", 100, 100), Some(Price::dollars(125)));
+        // Only the newer price was latest during the last 25
+        assert_eq!(twap("weighted", 25, 100), Some(Price::dollars(200)));
+    }
+
+    #[test]
+    fn starts_at_the_first_point_inside_the_window() {
+        record("late", Price::dollars(100), 60);
+        record("late", Price::dollars(300), 80);
+        assert_eq!(twap("late", 100, 100), Some(Price::dollars(200)));
+    }
+
+    #[test]
+    fn point_at_now_is_returned_as_is() {
+        record("now", Price::dollars(100), 100);
+        assert_eq!(twap("now", 10, 100), Some(Price::dollars(100)));
+    }
+
+    #[test]
+    fn feeds_do_not_mix() {
+        record("btc", Price::dollars(60_000), 0);
+        record("eth", Price::dollars(3_000), 0);
+        assert_eq!(twap("btc", 10, 10), Some(Price::dollars(60_000)));
+        assert_eq!(twap("eth", 10, 10), Some(Price::dollars(3_000)));
+    }
+
+    #[test]
+    fn drops_the_oldest_point_once_full() {
+        for timestamp in 0..=HISTORY_CAPACITY {
+            record("full", Price::dollars(timestamp + 1), timestamp);
+        }
+        let history = points("full", 0, u64::MAX);
+        assert_eq!(history.len() as u64, HISTORY_CAPACITY);
+        assert_eq!(history[0].timestamp, 1);
+
+        // Repeating a timestamp neither adds nor drops a point
+        record("full", Price::dollars(1), HISTORY_CAPACITY);
+        assert_eq!(points("full", 0, u64::MAX).len() as u64, HISTORY_CAPACITY);
+        assert_eq!(points("full", 0, u64::MAX)[0].timestamp, 1);
+    }
+}
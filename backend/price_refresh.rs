@@ -0,0 +1,169 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::{query, update};
+use ic_cdk_timers::TimerId;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::error::LendingError;
+use crate::oracle::{self, FeedConfig};
+use crate::price::Price;
+use crate::roles::{require_role, Role};
+
+// Failed refreshes are retried with a doubling delay up to this cap
+const MAX_BACKOFF: u64 = 30 * 60 * 1_000_000_000;
+
+/// Outcome of the latest price refreshes, scheduled or manual
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct RefreshStatus {
+    pub last_success: Option<u64>,
+    pub last_failure: Option<u64>,
+    pub last_error: Option<LendingError>,
+    pub consecutive_failures: u32,
+    pub next_refresh: Option<u64>,
+}
+
+// Timers do not survive upgrades, so neither does their status
+thread_local! {
+    static STATUS: RefCell<BTreeMap<String, RefreshStatus>> = const { RefCell::new(BTreeMap::new()) };
+    static TIMERS: RefCell<BTreeMap<String, TimerId>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+fn arm(symbol: String, delay: u64) {
+    let feed = symbol.clone();
+    let timer = ic_cdk_timers::set_timer(Duration::from_nanos(delay), move || {
+        ic_cdk::futures::spawn(scheduled_refresh(feed))
+    });
+    if let Some(previous) = TIMERS.with(|t| t.borrow_mut().insert(symbol.clone(), timer)) {
+        ic_cdk_timers::clear_timer(previous);
+    }
+    STATUS.with(|s| {
+        s.borrow_mut().entry(symbol).or_default().next_refresh = Some(ic_cdk::api::time() + delay)
+    });
+}
+
+/// Start refreshing every configured feed, called on install, after every upgrade
+/// and whenever the oracle config changes
+pub fn schedule() {
+    for (_, timer) in TIMERS.with(|t| std::mem::take(&mut *t.borrow_mut())) {
+        ic_cdk_timers::clear_timer(timer);
+    }
+    STATUS.with(|s| s.borrow_mut().clear());
+    for feed in oracle::config().feeds {
+        arm(feed.symbol, 0);
+    }
+}
+
+async fn refresh_now(feed: &FeedConfig) -> Result<Price, LendingError> {
+    let result = oracle::refresh(feed).await;
+    let now = ic_cdk::api::time();
+    STATUS.with(|s| {
+        let mut statuses = s.borrow_mut();
+        let status = statuses.entry(feed.symbol.clone()).or_default();
+        match &result {
+            Ok(_) => {
+                status.last_success = Some(now);
+                status.consecutive_failures = 0;
+            }
+            Err(err) => {
+                status.last_failure = Some(now);
+                status.last_error = Some(err.clone());
+                // The sources answered, keep reading at the normal pace to confirm the price
+                if !matches!(err, LendingError::PriceQuarantined { .. }) {
+                    status.consecutive_failures += 1;
+                }
+            }
+        }
+    });
+    result
+}
+
+async fn scheduled_refresh(symbol: String) {
+    let config = oracle::config();
+    // Feeds removed from the config stop being refreshed
+    let Some(feed) = config.feed(&symbol) else {
+        TIMERS.with(|t| t.borrow_mut().remove(&symbol));
+        STATUS.with(|s| s.borrow_mut().remove(&symbol));
+        return;
+    };
+    let _ = refresh_now(feed).await;
+    let failures = STATUS.with(|s| {
+        s.borrow()
+            .get(&symbol)
+            .map_or(0, |status| status.consecutive_failures)
+    });
+    let delay = retry_delay(
+        config.refresh_interval_of(feed),
+        failures,
+        config.max_price_age,
+    );
+    arm(symbol, delay);
+}
+
+// The first retry comes after the normal interval and later ones double, but never wait
+// more than half the max price age so the price is retried before it goes stale
+fn retry_delay(interval: u64, failures: u32, max_price_age: u64) -> u64 {
+    if failures == 0 {
+        return interval;
+    }
+    interval
+        .saturating_mul(1 << (failures - 1).min(16))
+        .min(MAX_BACKOFF)
+        .min(max_price_age / 2)
+}
+
+// ===== Canister Methods ===== //
+// Refreshes outside the schedule, which keeps running as planned
+#[update]
+async fn update_token_price(symbol: String) -> Result<Price, LendingError> {
+    require_role(Role::OracleReporter)?;
+    let feed = oracle::config()
+        .feed(&symbol)
+        .cloned()
+        .ok_or(LendingError::PriceFeedNotFound)?;
+    refresh_now(&feed).await
+}
+
+#[query]
+fn get_price_refresh_status() -> Vec<(String, RefreshStatus)> {
+    STATUS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(symbol, status)| (symbol.clone(), status.clone()))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: u64 = 60 * 1_000_000_000;
+
+    #[test]
+    fn retries_start_at_the_interval_and_double() {
+        let delays: Vec<u64> = (0..5)
+            .map(|failures| retry_delay(5 * MINUTE, failures, 120 * MINUTE))
+            .collect();
+        assert_eq!(
+            delays,
+            [
+                5 * MINUTE,
+                5 * MINUTE,
+                10 * MINUTE,
+                20 * MINUTE,
+                30 * MINUTE
+            ]
+        );
+        assert_eq!(retry_delay(5 * MINUTE, u32::MAX, 120 * MINUTE), 30 * MINUTE);
+    }
+
+    #[test]
+    fn retries_before_the_price_goes_stale() {
+        // With the defaults a failing feed is retried at least every 30 minutes
+        assert_eq!(retry_delay(5 * MINUTE, 4, 60 * MINUTE), 30 * MINUTE);
+        assert_eq!(retry_delay(5 * MINUTE, 3, 20 * MINUTE), 10 * MINUTE);
+        // Slow feeds retry sooner than their interval
+        assert_eq!(retry_delay(60 * MINUTE, 1, 60 * MINUTE), 30 * MINUTE);
+    }
+}
@@ -0,0 +1,171 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::query;
+
+use crate::interest::{self, RAY};
+use crate::risk;
+use crate::{Asset, BPS};
+
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+/// Kinked interest rate curve, all values in basis points per year
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RateModel {
+    pub base_rate_bps: u64,
+    /// Rate added between 0% and the optimal utilization
+    pub slope_low_bps: u64,
+    /// Rate added between the optimal utilization and 100%
+    pub slope_high_bps: u64,
+    pub optimal_utilization_bps: u64,
+}
+
+impl RateModel {
+    pub fn default_for(asset: Asset) -> Self {
+        match asset {
+            Asset::CkBTC => RateModel {
+                base_rate_bps: 0,
+                slope_low_bps: 400,
+                slope_high_bps: 30_000,
+                optimal_utilization_bps: 4_500,
+            },
+            Asset::CkUSDT => RateModel {
+                base_rate_bps: 0,
+                slope_low_bps: 400,
+                slope_high_bps: 7_500,
+                optimal_utilization_bps: 8_000,
+            },
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.optimal_utilization_bps == 0 || self.optimal_utilization_bps >= BPS {
+            return Err("Optimal utilization must be between 0% and 100%".to_string());
+        }
+        if self.base_rate_bps + self.slope_low_bps + self.slope_high_bps > 100 * BPS {
+            return Err("Maximum borrow rate cannot exceed 10000%".to_string());
+        }
+        Ok(())
+    }
+
+    /// Yearly borrow rate at `utilization_bps`, in basis points
+    pub fn borrow_rate_bps(&self, utilization_bps: u64) -> u64 {
+        let optimal = self.optimal_utilization_bps;
+        if utilization_bps <= optimal {
+            self.base_rate_bps + self.slope_low_bps * utilization_bps / optimal
+        } else {
+            let excess = utilization_bps - optimal;
+            self.base_rate_bps + self.slope_low_bps + self.slope_high_bps * excess / (BPS - optimal)
+        }
+    }
+}
+
+/// Current pool rates of one asset
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Rates {
+    pub utilization_bps: u64,
+    pub borrow_apr_bps: u64,
+    pub borrow_apy_bps: u64,
+    pub supply_apr_bps: u64,
+    pub supply_apy_bps: u64,
+}
+
+pub fn rate_model(asset: Asset) -> RateModel {
+    risk::risk_params().rate_model(asset)
+}
+
+/// Share of the supplied liquidity that is borrowed, in basis points
+pub fn utilization_bps(total_supply: u64, total_borrows: u64) -> u64 {
+    if total_supply == 0 {
+        return 0;
+    }
+    let utilization = total_borrows as u128 * BPS as u128 / total_supply as u128;
+    utilization.min(BPS as u128) as u64
+}
+
+/// Yearly borrow rate of `asset` in RAY
+pub fn borrow_rate_ray(asset: Asset, total_supply: u64, total_borrows: u64) -> u128 {
+    let utilization = utilization_bps(total_supply, total_borrows);
+    rate_model(asset).borrow_rate_bps(utilization) as u128 * RAY / BPS as u128
+}
+
+// Interest compounds on every accrual, so APY is close to continuous compounding
+fn apy_bps(apr_bps: u64) -> u64 {
+    let per_second = apr_bps as f64 / BPS as f64 / SECONDS_PER_YEAR;
+    (((1.0 + per_second).powf(SECONDS_PER_YEAR) - 1.0) * BPS as f64) as u64
+}
+
+// ===== Canister Methods ===== //
+#[query]
+fn get_rates(asset: Asset) -> Rates {
+    let market = interest::current(asset);
+    let total_borrows = interest::to_debt(market.total_scaled_debt, market.index);
+    let utilization = utilization_bps(market.total_supply, total_borrows);
+    let params = risk::risk_params();
+    let borrow_apr = params.rate_model(asset).borrow_rate_bps(utilization);
+    // Lenders earn the interest paid on the borrowed share, minus the reserve factor
+    let supply_apr =
+        borrow_apr * utilization / BPS * (BPS - params.reserve_factor_bps(asset)) / BPS;
+    Rates {
+        utilization_bps: utilization,
+        borrow_apr_bps: borrow_apr,
+        borrow_apy_bps: apy_bps(borrow_apr),
+        supply_apr_bps: supply_apr,
+        supply_apy_bps: apy_bps(supply_apr),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model() -> RateModel {
+        RateModel {
+            base_rate_bps: 100,
+            slope_low_bps: 400,
+            slope_high_bps: 7_500,
+            optimal_utilization_bps: 8_000,
+        }
+    }
+
+    #[test]
+    fn rises_along_the_low_slope_up_to_the_kink() {
+        let model = model();
+        assert_eq!(model.borrow_rate_bps(0), 100);
+        assert_eq!(model.borrow_rate_bps(4_000), 300);
+        assert_eq!(model.borrow_rate_bps(7_999), 499);
+        assert_eq!(model.borrow_rate_bps(8_000), 500);
+    }
+
+    #[test]
+    fn switches_to_the_high_slope_past_the_kink() {
+        let model = model();
+        assert_eq!(model.borrow_rate_bps(8_001), 503);
+        assert_eq!(model.borrow_rate_bps(9_000), 4_250);
+        assert_eq!(model.borrow_rate_bps(BPS), 8_000);
+    }
+
+    #[test]
+    fn never_decreases_with_utilization() {
+        let model = model();
+        let rates: Vec<u64> = (0..=BPS).map(|u| model.borrow_rate_bps(u)).collect();
+        assert!(rates.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn utilization_is_capped_and_safe_on_empty_pools() {
+        assert_eq!(utilization_bps(0, 0), 0);
+        assert_eq!(utilization_bps(0, 10), 0);
+        assert_eq!(utilization_bps(1_000, 250), 2_500);
+        assert_eq!(utilization_bps(1_000, 2_000), BPS);
+    }
+
+    #[test]
+    fn rejects_kinks_at_the_edges() {
+        let mut model = model();
+        model.optimal_utilization_bps = 0;
+        assert!(model.validate().is_err());
+        model.optimal_utilization_bps = BPS;
+        assert!(model.validate().is_err());
+        assert!(RateModel::default_for(Asset::CkUSDT).validate().is_ok());
+        assert!(RateModel::default_for(Asset::CkBTC).validate().is_ok());
+    }
+}
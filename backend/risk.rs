@@ -0,0 +1,221 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::query;
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use std::cell::RefCell;
+
+use crate::caps::MarketCaps;
+use crate::interest;
+use crate::rate_model::RateModel;
+use crate::{memory, Asset, LTVInfo, Memory, BPS};
+use crate::{RISK_PARAMS_AUDIT_MEMORY_ID, RISK_PARAMS_MEMORY_ID};
+
+// 10% of the interest paid by borrowers goes to the protocol
+const DEFAULT_RESERVE_FACTOR_BPS: u64 = 1_000;
+
+/// Risk parameters of the lending market
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RiskParams {
+    /// Maximum debt relative to collateral when borrowing or withdrawing
+    pub ltv: LTVInfo,
+    /// Debt relative to collateral above which a position can be liquidated
+    pub liquidation_threshold: LTVInfo,
+    /// Extra collateral seized on liquidation, in basis points of the repaid debt
+    pub liquidation_penalty_bps: u64,
+    /// Share of the seized penalty kept by the treasury, the liquidator earns the rest
+    pub liquidation_protocol_fee_bps: u64,
+    /// Fee charged on flash loans, in basis points of the amount
+    pub flash_loan_fee_bps: u64,
+    /// Canisters trusted to receive flash loans, the repayment cannot be enforced atomically
+    pub flash_loan_receivers: Vec<Principal>,
+    pub market_caps: Vec<(Asset, MarketCaps)>,
+    /// Maximum debt owed across all markets, valued in the debt asset
+    pub debt_ceiling: Option<u64>,
+    pub rate_models: Vec<(Asset, RateModel)>,
+    /// Share of the interest kept by the protocol, in basis points
+    pub reserve_factors: Vec<(Asset, u64)>,
+}
+
+impl Default for RiskParams {
+    fn default() -> Self {
+        RiskParams {
+            ltv: LTVInfo {
+                numerator: 50,
+                denominator: 100,
+            },
+            liquidation_threshold: LTVInfo {
+                numerator: 60,
+                denominator: 100,
+            },
+            liquidation_penalty_bps: 500,
+            liquidation_protocol_fee_bps: 1_000,
+            flash_loan_fee_bps: 9,
+            flash_loan_receivers: Vec::new(),
+            market_caps: Vec::new(),
+            debt_ceiling: None,
+            rate_models: Asset::ALL
+                .iter()
+                .map(|asset| (*asset, RateModel::default_for(*asset)))
+                .collect(),
+            reserve_factors: Asset::ALL
+                .iter()
+                .map(|asset| (*asset, DEFAULT_RESERVE_FACTOR_BPS))
+                .collect(),
+        }
+    }
+}
+
+impl RiskParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.ltv.denominator == 0 || self.liquidation_threshold.denominator == 0 {
+            return Err("Ratios must have a non-zero denominator".to_string());
+        }
+        if self.ltv.numerator == 0 {
+            return Err("LTV must be above 0%".to_string());
+        }
+        // Compare the ratios without dividing
+        let ltv = self.ltv.numerator as u128 * self.liquidation_threshold.denominator as u128;
+        let threshold = self.liquidation_threshold.numerator as u128 * self.ltv.denominator as u128;
+        if ltv >= threshold {
+            return Err("LTV must be below the liquidation threshold".to_string());
+        }
+        if self.liquidation_threshold.numerator >= self.liquidation_threshold.denominator {
+            return Err("Liquidation threshold must be below 100%".to_string());
+        }
+        if self.liquidation_penalty_bps > 2_000 {
+            return Err("Liquidation penalty cannot exceed 20%".to_string());
+        }
+        if self.liquidation_protocol_fee_bps > BPS {
+            return Err("Liquidation protocol fee cannot exceed 100%".to_string());
+        }
+        if self.flash_loan_fee_bps > 100 {
+            return Err("Flash loan fee cannot exceed 1%".to_string());
+        }
+        for (i, (asset, model)) in self.rate_models.iter().enumerate() {
+            if self.rate_models[..i].iter().any(|(a, _)| a == asset) {
+                return Err(format!("Duplicate rate model for {:?}", asset));
+            }
+            model.validate()?;
+        }
+        for (i, (asset, _)) in self.market_caps.iter().enumerate() {
+            if self.market_caps[..i].iter().any(|(a, _)| a == asset) {
+                return Err(format!("Duplicate caps for {:?}", asset));
+            }
+        }
+        for (i, (asset, reserve_factor_bps)) in self.reserve_factors.iter().enumerate() {
+            if self.reserve_factors[..i].iter().any(|(a, _)| a == asset) {
+                return Err(format!("Duplicate reserve factor for {:?}", asset));
+            }
+            if *reserve_factor_bps >= BPS {
+                return Err("Reserve factor must be below 100%".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn rate_model(&self, asset: Asset) -> RateModel {
+        self.rate_models
+            .iter()
+            .find(|(a, _)| *a == asset)
+            .map(|(_, model)| model.clone())
+            .unwrap_or_else(|| RateModel::default_for(asset))
+    }
+
+    pub fn market_caps(&self, asset: Asset) -> MarketCaps {
+        self.market_caps
+            .iter()
+            .find(|(a, _)| *a == asset)
+            .map(|(_, caps)| caps.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn reserve_factor_bps(&self, asset: Asset) -> u64 {
+        self.reserve_factors
+            .iter()
+            .find(|(a, _)| *a == asset)
+            .map_or(DEFAULT_RESERVE_FACTOR_BPS, |(_, bps)| *bps)
+    }
+
+    /// `amount` scaled by the liquidation penalty
+    pub fn liquidation_penalty(&self, amount: u64) -> u64 {
+        (amount as u128 * self.liquidation_penalty_bps as u128 / BPS as u128) as u64
+    }
+
+    /// Treasury share of a liquidation `penalty`, rounded down in favor of liquidators
+    pub fn liquidation_protocol_fee(&self, penalty: u64) -> u64 {
+        (penalty as u128 * self.liquidation_protocol_fee_bps as u128 / BPS as u128) as u64
+    }
+
+    /// Fee owed on a flash loan of `amount`, rounded up
+    pub fn flash_loan_fee(&self, amount: u64) -> u64 {
+        (amount as u128 * self.flash_loan_fee_bps as u128).div_ceil(BPS as u128) as u64
+    }
+}
+
+/// One change of the risk parameters
+#[derive(CandidType, Deserialize, Clone)]
+pub struct RiskParamsChange {
+    pub id: u64,
+    pub caller: Principal,
+    pub timestamp: u64,
+    pub old: RiskParams,
+    pub new: RiskParams,
+}
+
+impl_candid_storable!(RiskParams, RiskParamsChange);
+
+thread_local! {
+    static RISK_PARAMS: RefCell<StableCell<RiskParams, Memory>> = RefCell::new(
+        StableCell::init(memory(RISK_PARAMS_MEMORY_ID), RiskParams::default())
+            .expect("Failed to init risk params")
+    );
+
+    static AUDIT_LOG: RefCell<StableBTreeMap<u64, RiskParamsChange, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(RISK_PARAMS_AUDIT_MEMORY_ID))
+    );
+}
+
+pub fn risk_params() -> RiskParams {
+    RISK_PARAMS.with(|p| p.borrow().get().clone())
+}
+
+/// Replace the risk parameters, `caller` is recorded in the audit log
+pub fn set_risk_params(params: RiskParams, caller: Principal) -> Result<(), String> {
+    params.validate()?;
+
+    // Settle interest at the old rates before switching
+    for asset in Asset::ALL {
+        interest::accrue(asset);
+    }
+
+    let old = RISK_PARAMS.with(|p| {
+        p.borrow_mut()
+            .set(params.clone())
+            .expect("Failed to save risk params")
+    });
+    AUDIT_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let id = log.len();
+        log.insert(
+            id,
+            RiskParamsChange {
+                id,
+                caller,
+                timestamp: ic_cdk::api::time(),
+                old,
+                new: params,
+            },
+        );
+    });
+    Ok(())
+}
+
+// ===== Canister Methods ===== //
+#[query]
+fn get_risk_params() -> RiskParams {
+    risk_params()
+}
+
+#[query]
+fn get_risk_params_audit_log() -> Vec<RiskParamsChange> {
+    AUDIT_LOG.with(|log| log.borrow().iter().map(|(_, change)| change).collect())
+}
@@ -0,0 +1,95 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::error::LendingError;
+use crate::{memory, Memory, ROLES_MEMORY_ID};
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Admin,
+    RiskManager,
+    Pauser,
+    OracleReporter,
+    Treasurer,
+}
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct RoleKey {
+    principal: Principal,
+    role: Role,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+struct RoleGrant {
+    granted_by: Principal,
+    granted_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct RoleAssignment {
+    pub principal: Principal,
+    pub role: Role,
+    pub granted_by: Principal,
+    pub granted_at: u64,
+}
+
+impl_candid_storable!(Role, RoleKey, RoleGrant);
+
+thread_local! {
+    static ROLES: RefCell<StableBTreeMap<RoleKey, RoleGrant, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(ROLES_MEMORY_ID))
+    );
+}
+
+/// Controllers hold every role so they can bootstrap the first admins
+pub fn has_role(principal: Principal, role: Role) -> bool {
+    ic_cdk::api::is_controller(&principal)
+        || ROLES.with(|r| r.borrow().contains_key(&RoleKey { principal, role }))
+}
+
+/// Fails unless the caller holds `role`
+pub fn require_role(role: Role) -> Result<(), LendingError> {
+    if has_role(ic_cdk::api::msg_caller(), role) {
+        Ok(())
+    } else {
+        Err(LendingError::Unauthorized { role })
+    }
+}
+
+// ===== Canister Methods ===== //
+#[update]
+fn grant_role(principal: Principal, role: Role) -> Result<(), LendingError> {
+    require_role(Role::Admin)?;
+    let grant = RoleGrant {
+        granted_by: ic_cdk::api::msg_caller(),
+        granted_at: ic_cdk::api::time(),
+    };
+    ROLES.with(|r| r.borrow_mut().insert(RoleKey { principal, role }, grant));
+    Ok(())
+}
+
+#[update]
+fn revoke_role(principal: Principal, role: Role) -> Result<(), LendingError> {
+    require_role(Role::Admin)?;
+    ROLES
+        .with(|r| r.borrow_mut().remove(&RoleKey { principal, role }))
+        .map(|_| ())
+        .ok_or(LendingError::RoleNotGranted)
+}
+
+#[query]
+fn list_roles() -> Vec<RoleAssignment> {
+    ROLES.with(|r| {
+        r.borrow()
+            .iter()
+            .map(|(key, grant)| RoleAssignment {
+                principal: key.principal,
+                role: key.role,
+                granted_by: grant.granted_by,
+                granted_at: grant.granted_at,
+            })
+            .collect()
+    })
+}
@@ -0,0 +1,113 @@
+use candid::{CandidType, Deserialize, Nat};
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::error::LendingError;
+use crate::risk;
+use crate::roles::{require_role, Role};
+use crate::{icrc1_transfer, memory, Account, Asset, Memory, BPS, TREASURY_MEMORY_ID};
+
+/// Protocol revenue collected in one asset
+#[derive(CandidType, Deserialize, Default, Clone)]
+pub struct ProtocolRevenue {
+    pub interest: u64,
+    pub liquidation_penalties: u64,
+    pub flash_loan_fees: u64,
+    /// Paid out to treasurers or moved into the bad debt reserve
+    pub withdrawn: u64,
+    /// Revenue still held by the canister
+    pub balance: u64,
+}
+
+impl_candid_storable!(ProtocolRevenue);
+
+thread_local! {
+    static TREASURY: RefCell<StableBTreeMap<Asset, ProtocolRevenue, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(TREASURY_MEMORY_ID))
+    );
+}
+
+fn update_revenue(asset: Asset, f: impl FnOnce(&mut ProtocolRevenue)) {
+    TREASURY.with(|t| {
+        let mut map = t.borrow_mut();
+        let mut revenue = map.get(&asset).unwrap_or_default();
+        f(&mut revenue);
+        map.insert(asset, revenue);
+    });
+}
+
+/// Protocol share of `interest`, rounded down in favor of lenders
+pub fn reserve_share(asset: Asset, interest: u64) -> u64 {
+    let reserve_factor_bps = risk::risk_params().reserve_factor_bps(asset);
+    (interest as u128 * reserve_factor_bps as u128 / BPS as u128) as u64
+}
+
+pub fn record_interest(asset: Asset, amount: u64) {
+    if amount > 0 {
+        update_revenue(asset, |r| {
+            r.interest += amount;
+            r.balance += amount;
+        });
+    }
+}
+
+pub fn record_flash_loan_fee(asset: Asset, amount: u64) {
+    if amount > 0 {
+        update_revenue(asset, |r| {
+            r.flash_loan_fees += amount;
+            r.balance += amount;
+        });
+    }
+}
+
+pub fn record_liquidation_penalty(asset: Asset, amount: u64) {
+    if amount > 0 {
+        update_revenue(asset, |r| {
+            r.liquidation_penalties += amount;
+            r.balance += amount;
+        });
+    }
+}
+
+/// Take `amount` of revenue out of the treasury to use inside the protocol
+pub fn take_revenue(asset: Asset, amount: u64) -> Result<(), LendingError> {
+    let balance = TREASURY.with(|t| t.borrow().get(&asset).unwrap_or_default().balance);
+    if amount > balance {
+        return Err(LendingError::InsufficientTreasuryBalance { balance });
+    }
+    update_revenue(asset, |r| {
+        r.balance -= amount;
+        r.withdrawn += amount;
+    });
+    Ok(())
+}
+
+// ===== Canister Methods ===== //
+#[query]
+fn get_reserve_factor(asset: Asset) -> u64 {
+    risk::risk_params().reserve_factor_bps(asset)
+}
+
+#[query]
+fn get_protocol_revenue() -> Vec<(Asset, ProtocolRevenue)> {
+    TREASURY.with(|t| t.borrow().iter().collect())
+}
+
+#[update]
+async fn withdraw_revenue(asset: Asset, to: Account, amount: u64) -> Result<Nat, LendingError> {
+    require_role(Role::Treasurer)?;
+    if amount == 0 {
+        return Err(LendingError::InvalidAmount);
+    }
+    // Deduct before the transfer so concurrent calls cannot withdraw twice
+    take_revenue(asset, amount)?;
+    let result = icrc1_transfer(asset, to, Nat::from(amount)).await;
+    if result.is_err() {
+        update_revenue(asset, |r| {
+            r.balance += amount;
+            r.withdrawn -= amount;
+        });
+    }
+    result
+}
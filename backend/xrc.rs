@@ -0,0 +1,103 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::call::Call;
+
+use crate::error::LendingError;
+use crate::price::Price;
+
+// The exchange rate canister charges 1B cycles per request and refunds what it does not use
+const XRC_CYCLES: u128 = 1_000_000_000;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum XrcAssetClass {
+    Cryptocurrency,
+    FiatCurrency,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct XrcAsset {
+    pub symbol: String,
+    pub class: XrcAssetClass,
+}
+
+/// Exchange rate canister used as a price source
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct XrcSource {
+    pub canister_id: Principal,
+    pub base_asset: XrcAsset,
+    pub quote_asset: XrcAsset,
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetExchangeRateRequest {
+    base_asset: XrcAsset,
+    quote_asset: XrcAsset,
+    timestamp: Option<u64>,
+}
+
+// Only the fields needed to read the rate, candid skips the rest
+#[derive(CandidType, Deserialize)]
+struct ExchangeRateMetadata {
+    decimals: u32,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ExchangeRate {
+    rate: u64,
+    metadata: ExchangeRateMetadata,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum ExchangeRateError {
+    AnonymousPrincipalNotAllowed,
+    Pending,
+    CryptoBaseAssetNotFound,
+    CryptoQuoteAssetNotFound,
+    StablecoinRateNotFound,
+    StablecoinRateTooFewRates,
+    StablecoinRateZeroRate,
+    ForexInvalidTimestamp,
+    ForexBaseAssetNotFound,
+    ForexQuoteAssetNotFound,
+    ForexAssetsNotFound,
+    RateLimited,
+    NotEnoughCycles,
+    FailedToAcceptCycles,
+    InconsistentRatesReceived,
+    Other { code: u32, description: String },
+}
+
+#[derive(CandidType, Deserialize)]
+enum GetExchangeRateResult {
+    Ok(ExchangeRate),
+    Err(ExchangeRateError),
+}
+
+/// Latest rate of the source
+pub async fn fetch_rate(source: &XrcSource) -> Result<Price, LendingError> {
+    let request = GetExchangeRateRequest {
+        base_asset: source.base_asset.clone(),
+        quote_asset: source.quote_asset.clone(),
+        timestamp: None,
+    };
+    let result: GetExchangeRateResult =
+        Call::unbounded_wait(source.canister_id, "get_exchange_rate")
+            .with_arg(request)
+            .with_cycles(XRC_CYCLES)
+            .await
+            .map_err(|e| LendingError::CallFailed(e.to_string()))?
+            .candid()
+            .map_err(|e| LendingError::CallFailed(e.to_string()))?;
+
+    let rate = match result {
+        GetExchangeRateResult::Ok(rate) => rate,
+        GetExchangeRateResult::Err(err) => {
+            return Err(LendingError::CallFailed(format!(
+                "Exchange rate canister error: {:?}",
+                err
+            )))
+        }
+    };
+    Price::from_parts(rate.rate as u128, rate.metadata.decimals)
+        .filter(|price| !price.is_zero())
+        .ok_or_else(|| LendingError::CallFailed("Exchange rate out of range".to_string()))
+}
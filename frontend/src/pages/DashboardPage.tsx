@@ -222,6 +222,7 @@ function TransactionSection() {
 		denominator: number;
 	}
 	interface LoanInfo {
+		position_id: bigint;
 		collateral: number;
 		debt: number;
 	}
@@ -241,15 +242,31 @@ function TransactionSection() {
 	const [borrowAmount, setBorrowAmount] = useState<number>(0);
 	const [repayAmount, setRepayAmount] = useState<number>(0);
 
+	// Update methods return a Candid Result, surface the error variant name
+	const unwrap = (result: any) => {
+		if ("Err" in result) {
+			throw new Error(Object.keys(result.Err)[0]);
+		}
+		return result.Ok;
+	};
+
 	const getBalance = async () => {
-		const resultBalance = await backend.get_balances();
-		setLoanInfo(resultBalance[0][1]);
+		const positions: LoanInfo[] = await backend.list_my_positions();
+		setLoanInfo(positions[0] ?? null);
+	};
+
+	// The dashboard works on the caller's first position and opens one on first use
+	const positionId = async (): Promise<bigint> => {
+		if (loanInfo) {
+			return loanInfo.position_id;
+		}
+		return unwrap(await backend.open_position());
 	};
 
 	const handleDeposit = async () => {
 		try {
 			await agent.fetchRootKey(); // Optionally fetch root key if local
-			await backend.deposit(depositAmount);
+			unwrap(await backend.deposit(await positionId(), depositAmount));
 			setDepositAmount(0);
 			getBalance();
 		} catch (error) {
@@ -260,7 +277,7 @@ function TransactionSection() {
 	const handleWithdraw = async () => {
 		try {
 			await agent.fetchRootKey(); // Optionally fetch root key if local
-			await backend.withdraw(withdrawAmount);
+			unwrap(await backend.withdraw(await positionId(), withdrawAmount));
 			setWithdrawAmount(0);
 			getBalance();
 		} catch (error) {
@@ -271,7 +288,7 @@ function TransactionSection() {
 	const handleBorrow = async () => {
 		try {
 			await agent.fetchRootKey(); // Optionally fetch root key if local
-			await backend.borrow(borrowAmount);
+			unwrap(await backend.borrow(await positionId(), borrowAmount));
 			setBorrowAmount(0);
 			getBalance();
 		} catch (error) {
@@ -282,7 +299,7 @@ function TransactionSection() {
 	const handleRepay = async () => {
 		try {
 			await agent.fetchRootKey(); // Optionally fetch root key if local
-			await backend.repay(repayAmount);
+			unwrap(await backend.repay(await positionId(), repayAmount));
 			setRepayAmount(0);
 			getBalance();
 		} catch (error) {
@@ -0,0 +1,111 @@
+// Local stand-in for the exchange rate canister (XRC). It answers `get_exchange_rate`
+// with a rate set through `set_exchange_rate` so the oracle can be tested without mainnet.
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::{export_candid, query, update};
+use std::cell::RefCell;
+
+#[derive(CandidType, Deserialize, Clone)]
+pub enum AssetClass {
+    Cryptocurrency,
+    FiatCurrency,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Asset {
+    pub symbol: String,
+    pub class: AssetClass,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct GetExchangeRateRequest {
+    pub base_asset: Asset,
+    pub quote_asset: Asset,
+    pub timestamp: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ExchangeRateMetadata {
+    pub decimals: u32,
+    pub base_asset_num_received_rates: u64,
+    pub base_asset_num_queried_sources: u64,
+    pub quote_asset_num_received_rates: u64,
+    pub quote_asset_num_queried_sources: u64,
+    pub standard_deviation: u64,
+    pub forex_timestamp: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ExchangeRate {
+    pub base_asset: Asset,
+    pub quote_asset: Asset,
+    pub timestamp: u64,
+    pub rate: u64,
+    pub metadata: ExchangeRateMetadata,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum ExchangeRateError {
+    AnonymousPrincipalNotAllowed,
+    Pending,
+    CryptoBaseAssetNotFound,
+    CryptoQuoteAssetNotFound,
+    StablecoinRateNotFound,
+    StablecoinRateTooFewRates,
+    StablecoinRateZeroRate,
+    ForexInvalidTimestamp,
+    ForexBaseAssetNotFound,
+    ForexQuoteAssetNotFound,
+    ForexAssetsNotFound,
+    RateLimited,
+    NotEnoughCycles,
+    FailedToAcceptCycles,
+    InconsistentRatesReceived,
+    Other { code: u32, description: String },
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum GetExchangeRateResult {
+    Ok(ExchangeRate),
+    Err(ExchangeRateError),
+}
+
+thread_local! {
+    // Rate and its number of decimals, unset until a test configures it
+    static RATE: RefCell<Option<(u64, u32)>> = const { RefCell::new(None) };
+}
+
+#[update]
+fn set_exchange_rate(rate: u64, decimals: u32) {
+    RATE.with(|r| *r.borrow_mut() = Some((rate, decimals)));
+}
+
+#[query]
+fn get_configured_rate() -> Option<(u64, u32)> {
+    RATE.with(|r| *r.borrow())
+}
+
+// Any pair gets the configured rate, attached cycles are left to be refunded
+#[update]
+fn get_exchange_rate(request: GetExchangeRateRequest) -> GetExchangeRateResult {
+    let Some((rate, decimals)) = RATE.with(|r| *r.borrow()) else {
+        return GetExchangeRateResult::Err(ExchangeRateError::CryptoBaseAssetNotFound);
+    };
+    let timestamp = ic_cdk::api::time() / 1_000_000_000;
+    GetExchangeRateResult::Ok(ExchangeRate {
+        base_asset: request.base_asset,
+        quote_asset: request.quote_asset,
+        timestamp: request.timestamp.unwrap_or(timestamp - timestamp % 60),
+        rate,
+        metadata: ExchangeRateMetadata {
+            decimals,
+            base_asset_num_received_rates: 1,
+            base_asset_num_queried_sources: 1,
+            quote_asset_num_received_rates: 1,
+            quote_asset_num_queried_sources: 1,
+            standard_deviation: 0,
+            forex_timestamp: None,
+        },
+    })
+}
+
+export_candid!();